@@ -9,8 +9,20 @@ async fn main() -> Result<()> {
         .build();
     let client = Client::from_conf(config);
 
-    println!("\n--- Calling echo_message operation without authentication");
+    println!("\n--- Calling echo_message operation without authentication, it is unauthorized");
     let ret = client.echo_message().message("example").send().await;
+    if let Err(e) = &ret {
+        println!("{:?}", e.as_service_error());
+    }
+
+    println!("\n--- Calling signup operation to create an account");
+
+    let ret = client
+        .signup()
+        .username("test")
+        .password("abcd12345")
+        .send()
+        .await;
     println!("{:?}", ret);
 
     println!("\n--- Calling signin operation to get a token");
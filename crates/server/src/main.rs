@@ -1,5 +1,5 @@
 use anyhow::Result;
-use echo_service::{get_router, AppConfig};
+use echo_service::{get_router, serve_tls, AppConfig};
 use std::net::SocketAddr;
 use tracing::info;
 
@@ -7,11 +7,22 @@ use tracing::info;
 pub async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
 
-    let app = get_router(AppConfig::default()).await;
-    let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
+    // without a config file, keys are generated and tokens don't survive a restart
+    let config = match std::env::var("ECHO_CONFIG") {
+        Ok(path) => AppConfig::load(path)?,
+        Err(_) => AppConfig::default(),
+    };
+    let addr = SocketAddr::from(([0, 0, 0, 0], config.port));
+    let tls = config.tls.clone();
+    let app = get_router(config).await?;
     info!("Listening on {}", addr);
+    if let Some(tls) = tls {
+        serve_tls(app, addr, &tls).await?;
+        return Ok(());
+    }
     axum::Server::bind(&addr)
-        .serve(app.into_make_service())
+        // connect info provides the client ip for signin throttling
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await?;
     Ok(())
 }
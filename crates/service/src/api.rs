@@ -1,14 +1,27 @@
-use crate::{forbidden, AppState};
+use crate::{
+    audit::{AuditContext, AuditEvent},
+    auth::{
+        dummy_verify_password, hash_password, verify_password, AuthError, CustomClaims, Identity,
+        Purpose, PurposeToken,
+    },
+    conflict, err, forbidden,
+    middleware::{ClientIp, IssuedSession, UserAgent},
+    not_found,
+    store::{self, ApiKey, Session, StoreError, User},
+    throttled, try_err, unauthorized, AppState, Notification,
+};
 use aws_smithy_http_server::Extension;
-use echo_server_sdk::{error, input, output};
-use std::sync::Arc;
-use tracing::info;
+use echo_server_sdk::{error, input, model, output};
+use std::{net::IpAddr, sync::Arc};
+use tracing::{debug, info, warn};
 
 pub async fn echo_message(
     input: input::EchoMessageInput,
     Extension(_state): Extension<Arc<AppState>>,
+    identity: Option<Identity>,
 ) -> Result<output::EchoMessageOutput, error::EchoMessageError> {
-    info!("echo: {:?}", input);
+    let caller = identity.map(|i| i.username);
+    info!("echo from {:?}: {:?}", caller, input);
     let message = input.message;
     let output = output::EchoMessageOutput { message };
     Ok(output)
@@ -17,13 +30,645 @@ pub async fn echo_message(
 pub async fn signin(
     input: input::SigninInput,
     Extension(state): Extension<Arc<AppState>>,
+    ClientIp(ip): ClientIp,
+    UserAgent(user_agent): UserAgent,
+    audit: AuditContext,
+    session: IssuedSession,
+) -> Result<output::SigninOutput, error::SigninError> {
+    let mut principal = input.username.clone();
+    let result = signin_steps(&state, input, ip, user_agent.as_deref(), &mut principal).await;
+    let mut record = audit.record(AuditEvent::Signin, principal.as_deref(), &result);
+    if matches!(&result, Ok(out) if out.challenge_token.is_some()) {
+        record = record.with_detail("mfa challenge issued");
+    }
+    state.audit(record);
+    if let Ok(output::SigninOutput {
+        token: Some(token),
+        expires_in: Some(expires_in),
+        ..
+    }) = &result
+    {
+        session.set(token, *expires_in as u64);
+    }
+    result
+}
+
+/// Signin with the password or the MFA step, `principal` is set once the user is known.
+async fn signin_steps(
+    state: &AppState,
+    input: input::SigninInput,
+    ip: Option<IpAddr>,
+    user_agent: Option<&str>,
+    principal: &mut Option<String>,
 ) -> Result<output::SigninOutput, error::SigninError> {
-    info!("signin: {:?}", input);
     let signer = &state.signer;
-    let username = input.username;
-    if input.password.len() < 8 {
-        forbidden!("invalid password");
+    let user = match &input.challenge_token {
+        Some(challenge) => {
+            let challenge = state
+                .verifier
+                .verify_for(Purpose::MfaChallenge, challenge)?;
+            *principal = Some(challenge.username.clone());
+            signin_mfa(state, &challenge, input.mfa_code.as_deref(), ip)?
+        }
+        None => {
+            let (Some(username), Some(password)) = (&input.username, &input.password) else {
+                unauthorized!("username and password are required");
+            };
+            let user = signin_password(state, username, password, ip).await?;
+            if user.mfa.as_ref().map_or(false, |mfa| mfa.enabled) {
+                let lifetime = state.config.auth.mfa.challenge_lifetime_seconds;
+                let challenge_token =
+                    signer.sign_for(Purpose::MfaChallenge, &user.username, lifetime)?;
+                return Ok(output::SigninOutput {
+                    token: None,
+                    refresh_token: None,
+                    expires_in: None,
+                    challenge_token: Some(challenge_token),
+                });
+            }
+            user
+        }
+    };
+    // failures are only forgotten once every factor is verified
+    state.signin_attempts.succeeded(&user.username);
+    let session = state.sessions.start(&user.username, user_agent, ip)?;
+    let lifetime = signer.lifetime(input.token_lifetime_seconds.map(|s| s.into_inner() as u64));
+    let token = signer.sign(claims_of(state, &user, &session.id), lifetime)?;
+    let refresh_token = state.refresh_tokens.issue(&user.username, &session.id)?;
+    Ok(output::SigninOutput {
+        token: Some(token),
+        refresh_token: Some(refresh_token),
+        expires_in: Some(i32::try_from(lifetime).unwrap_or(i32::MAX)),
+        challenge_token: None,
+    })
+}
+
+/// First signin step, the user of a valid username and password.
+async fn signin_password(
+    state: &AppState,
+    username: &str,
+    password: &str,
+    ip: Option<IpAddr>,
+) -> Result<User, error::SigninError> {
+    info!("signin: {} from {:?}", username, ip);
+    let attempts = &state.signin_attempts;
+    if let Some(retry_after) = attempts.locked(username, ip) {
+        throttled!(
+            retry_after,
+            "too many failed attempts, retry in {} seconds",
+            retry_after
+        );
+    }
+    let user = try_err!(state.users.get(username), Database);
+    let Some(user) = user else {
+        dummy_verify_password(password).await;
+        attempts.failed(username, ip);
+        unauthorized!("invalid username or password");
+    };
+    if !verify_password(password, &user.password_hash).await {
+        attempts.failed(username, ip);
+        unauthorized!("invalid username or password");
+    }
+    if user.disabled {
+        forbidden!("user {} is disabled", user.username);
+    }
+    Ok(user)
+}
+
+/// Second signin step of users with MFA, the user of a verified challenge token and a valid MFA code.
+fn signin_mfa(
+    state: &AppState,
+    challenge: &PurposeToken,
+    code: Option<&str>,
+    ip: Option<IpAddr>,
+) -> Result<User, error::SigninError> {
+    let username = &challenge.username;
+    info!("signin mfa: {} from {:?}", username, ip);
+    let attempts = &state.signin_attempts;
+    if let Some(retry_after) = attempts.locked(username, ip) {
+        throttled!(
+            retry_after,
+            "too many failed attempts, retry in {} seconds",
+            retry_after
+        );
+    }
+    let Some(code) = code else {
+        unauthorized!("mfa code is required");
+    };
+    let mut user = match try_err!(state.users.get(username), Database) {
+        Some(user) if !user.disabled => user,
+        _ => forbidden!("user {} is disabled or removed", username),
+    };
+    let config = &state.config.auth.mfa;
+    let verified = match user.mfa.as_mut() {
+        Some(mfa) if mfa.enabled => try_err!(config.verify(mfa, code, store::now()), Unknown),
+        _ => false,
+    };
+    if !verified {
+        attempts.failed(username, ip);
+        unauthorized!("invalid mfa code");
+    }
+    // persist the used code so it can't be accepted again
+    try_err!(state.users.update(user.clone()), Database);
+    state.verifier.consume(challenge)?;
+    Ok(user)
+}
+
+pub async fn signup(
+    input: input::SignupInput,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Result<output::SignupOutput, error::SignupError> {
+    let username = input.username.into_inner();
+    info!("signup: {}", username);
+    let password_hash = try_err!(hash_password(input.password.as_str()).await, Unknown);
+    let user = User {
+        username: username.clone(),
+        password_hash,
+        roles: state.config.auth.roles.default_roles.clone(),
+        disabled: false,
+        created_at: store::now(),
+        mfa: None,
+    };
+    match state.users.insert(user) {
+        Ok(()) => Ok(output::SignupOutput { username }),
+        Err(StoreError::AlreadyExists(_)) => conflict!("username {} is already taken", username),
+        Err(e) => err!(Database, e.to_string()),
+    }
+}
+
+pub async fn refresh_token(
+    input: input::RefreshTokenInput,
+    Extension(state): Extension<Arc<AppState>>,
+    audit: AuditContext,
+    session: IssuedSession,
+) -> Result<output::RefreshTokenOutput, error::RefreshTokenError> {
+    let mut principal = None;
+    let result = refresh(&state, &input.refresh_token, &mut principal);
+    state.audit(audit.record(AuditEvent::TokenRefresh, principal.as_deref(), &result));
+    if let Ok(out) = &result {
+        session.set(&out.token, out.expires_in as u64);
+    }
+    result
+}
+
+/// Rotate a refresh token, `principal` is set once the user is known.
+fn refresh(
+    state: &AppState,
+    refresh_token: &str,
+    principal: &mut Option<String>,
+) -> Result<output::RefreshTokenOutput, error::RefreshTokenError> {
+    let current = state.refresh_tokens.get(refresh_token)?;
+    *principal = Some(current.username.clone());
+    let username = &current.username;
+    info!("refresh token: {}", username);
+    // checked before rotating, so no new token joins the family of a disabled user or ended session
+    let user = match try_err!(state.users.get(username), Database) {
+        Some(user) if !user.disabled => user,
+        _ => forbidden!("user {} is disabled or removed", username),
+    };
+    state.sessions.ensure_active(&current.family)?;
+    let (old, refresh_token) = match state.refresh_tokens.rotate(refresh_token) {
+        Err(AuthError::RefreshTokenReused) => {
+            // the token was stolen, the access tokens of its session must not outlive its family
+            warn!("refresh token reused, ending session {}", current.family);
+            match end_session(state, &current.family, username) {
+                Ok(()) | Err(AuthError::InvalidSession) => {}
+                Err(e) => return Err(e.into()),
+            }
+            return Err(AuthError::RefreshTokenReused.into());
+        }
+        result => result?,
+    };
+    state.sessions.refreshed(&old.family)?;
+    let lifetime = state.signer.lifetime(None);
+    let token = state
+        .signer
+        .sign(claims_of(state, &user, &old.family), lifetime)?;
+    Ok(output::RefreshTokenOutput {
+        token,
+        refresh_token,
+        expires_in: i32::try_from(lifetime).unwrap_or(i32::MAX),
+    })
+}
+
+pub async fn signout(
+    input: input::SignoutInput,
+    Extension(state): Extension<Arc<AppState>>,
+    identity: Identity,
+    audit: AuditContext,
+) -> Result<output::SignoutOutput, error::SignoutError> {
+    let username = &identity.username;
+    info!("signout: {}", username);
+    let result = end_signin(&state, &identity, input.refresh_token.as_deref());
+    state.audit(audit.record(AuditEvent::Signout, Some(username), &result));
+    result?;
+    Ok(output::SignoutOutput {})
+}
+
+/// Revoke the token of the caller, and the refresh tokens and session of its signin.
+fn end_signin(
+    state: &AppState,
+    identity: &Identity,
+    refresh_token: Option<&str>,
+) -> Result<(), AuthError> {
+    let username = &identity.username;
+    state.verifier.revoke(identity)?;
+    if let Some(refresh_token) = refresh_token {
+        state.refresh_tokens.revoke(refresh_token, username)?;
+    }
+    if let Some(session_id) = &identity.session_id {
+        end_session(state, session_id, username)?;
+    }
+    Ok(())
+}
+
+pub async fn create_api_key(
+    input: input::CreateApiKeyInput,
+    Extension(state): Extension<Arc<AppState>>,
+    identity: Identity,
+) -> Result<output::CreateApiKeyOutput, error::CreateApiKeyError> {
+    info!("create api key: {} for {}", input.name, identity.username);
+    let scopes = match input.scopes {
+        Some(scopes) => {
+            if let Some(scope) = scopes.iter().find(|s| !identity.scopes.contains(s)) {
+                forbidden!("scope {} is not granted to {}", scope, identity.username);
+            }
+            scopes
+        }
+        None => identity.scopes.clone(),
+    };
+    let lifetime = input.expires_in.map(|s| s.into_inner() as u64);
+    let (key, api_key) =
+        state
+            .api_keys
+            .create(&identity.username, &input.name, scopes, lifetime)?;
+    Ok(output::CreateApiKeyOutput {
+        api_key,
+        summary: summary_of(key),
+    })
+}
+
+pub async fn list_api_keys(
+    _input: input::ListApiKeysInput,
+    Extension(state): Extension<Arc<AppState>>,
+    identity: Identity,
+) -> Result<output::ListApiKeysOutput, error::ListApiKeysError> {
+    let keys = state.api_keys.list(&identity.username)?;
+    Ok(output::ListApiKeysOutput {
+        api_keys: keys.into_iter().map(summary_of).collect(),
+    })
+}
+
+pub async fn revoke_api_key(
+    input: input::RevokeApiKeyInput,
+    Extension(state): Extension<Arc<AppState>>,
+    identity: Identity,
+    audit: AuditContext,
+) -> Result<output::RevokeApiKeyOutput, error::RevokeApiKeyError> {
+    info!("revoke api key: {} of {}", input.id, identity.username);
+    let result = state.api_keys.revoke(&input.id, &identity.username);
+    let record = audit.record(AuditEvent::ApiKeyRevoked, Some(&identity.username), &result);
+    state.audit(record.with_detail(format!("api key {}", input.id)));
+    result?;
+    Ok(output::RevokeApiKeyOutput {})
+}
+
+pub async fn list_sessions(
+    _input: input::ListSessionsInput,
+    Extension(state): Extension<Arc<AppState>>,
+    identity: Identity,
+) -> Result<output::ListSessionsOutput, error::ListSessionsError> {
+    let sessions = state.sessions.list(&identity.username)?;
+    let current = identity.session_id.as_deref();
+    Ok(output::ListSessionsOutput {
+        sessions: sessions
+            .into_iter()
+            .map(|s| session_summary_of(s, current))
+            .collect(),
+    })
+}
+
+pub async fn revoke_session(
+    input: input::RevokeSessionInput,
+    Extension(state): Extension<Arc<AppState>>,
+    identity: Identity,
+    audit: AuditContext,
+) -> Result<output::RevokeSessionOutput, error::RevokeSessionError> {
+    info!("revoke session: {} of {}", input.id, identity.username);
+    let result = end_session(&state, &input.id, &identity.username);
+    let record = audit.record(
+        AuditEvent::SessionRevoked,
+        Some(&identity.username),
+        &result,
+    );
+    state.audit(record.with_detail(format!("session {}", input.id)));
+    result?;
+    Ok(output::RevokeSessionOutput {})
+}
+
+/// End a session of a user and the refresh tokens of its family.
+fn end_session(state: &AppState, id: &str, username: &str) -> Result<(), AuthError> {
+    state.sessions.revoke(id, username)?;
+    state.refresh_tokens.revoke_family(id)
+}
+
+/// End the sessions and refresh tokens of a user except the session `keep`, e.g. after a
+/// password change, so a stolen password or token is useless.
+fn end_other_sessions(
+    state: &AppState,
+    username: &str,
+    keep: Option<&str>,
+) -> Result<(), AuthError> {
+    for id in state.sessions.revoke_all(username, keep)? {
+        state.refresh_tokens.revoke_family(&id)?;
+    }
+    Ok(())
+}
+
+pub async fn introspect_token(
+    input: input::IntrospectTokenInput,
+    Extension(state): Extension<Arc<AppState>>,
+    identity: Identity,
+) -> Result<output::IntrospectTokenOutput, error::IntrospectTokenError> {
+    if let Some(issuer) = state.verifier.stale_issuer(&input.token) {
+        if let Err(e) = issuer.refresh().await {
+            warn!("failed to refresh keys of {}: {}", issuer.issuer(), e);
+        }
+    }
+    let token = state
+        .verifier
+        .authenticate(&input.token)
+        .and_then(|token| state.sessions.check(token));
+    let token = match token {
+        Ok(token) => token,
+        Err(AuthError::Store(e)) => return err!(Database, e.to_string()),
+        Err(e) => {
+            debug!("introspect by {}: inactive token: {}", identity.username, e);
+            return Ok(output::IntrospectTokenOutput {
+                active: false,
+                username: None,
+                scope: None,
+                roles: None,
+                token_type: None,
+                issuer: None,
+                subject: None,
+                audiences: None,
+                token_id: None,
+                issued_at: None,
+                expires_at: None,
+                not_before: None,
+            });
+        }
+    };
+    info!("introspect by {}: {}", identity.username, token.username);
+    Ok(output::IntrospectTokenOutput {
+        active: true,
+        scope: Some(token.scopes.join(" ")),
+        username: Some(token.username),
+        roles: Some(token.roles),
+        token_type: Some("Bearer".to_string()),
+        issuer: token.issuer,
+        subject: token.subject,
+        audiences: Some(token.audiences),
+        token_id: token.jwt_id,
+        issued_at: token.issued_at.map(|t| t as i64),
+        expires_at: token.expires_at.map(|t| t as i64),
+        not_before: token.not_before.map(|t| t as i64),
+    })
+}
+
+pub async fn change_password(
+    input: input::ChangePasswordInput,
+    Extension(state): Extension<Arc<AppState>>,
+    ClientIp(ip): ClientIp,
+    identity: Identity,
+) -> Result<output::ChangePasswordOutput, error::ChangePasswordError> {
+    let username = &identity.username;
+    info!("change password: {}", username);
+    // guessing the current password with a stolen token is throttled like signin
+    let attempts = &state.signin_attempts;
+    if let Some(retry_after) = attempts.locked(username, ip) {
+        throttled!(
+            retry_after,
+            "too many failed attempts, retry in {} seconds",
+            retry_after
+        );
+    }
+    let user = try_err!(state.users.get(username), Database);
+    let Some(mut user) = user else {
+        unauthorized!("user {} does not exist", username);
+    };
+    if !verify_password(&input.current_password, &user.password_hash).await {
+        attempts.failed(username, ip);
+        unauthorized!("invalid password");
+    }
+    attempts.succeeded(username);
+    user.password_hash = try_err!(hash_password(input.new_password.as_str()).await, Unknown);
+    try_err!(state.users.update(user), Database);
+    let current = identity.session_id.as_deref();
+    try_err!(end_other_sessions(&state, username, current), Database);
+    notify(
+        &state,
+        Notification::PasswordChanged {
+            username: username.clone(),
+        },
+    );
+    Ok(output::ChangePasswordOutput {})
+}
+
+pub async fn request_password_reset(
+    input: input::RequestPasswordResetInput,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Result<output::RequestPasswordResetOutput, error::RequestPasswordResetError> {
+    info!("request password reset: {}", input.username);
+    let user = try_err!(state.users.get(&input.username), Database);
+    match user {
+        Some(user) if !user.disabled => {
+            let lifetime = state.config.auth.reset.lifetime_seconds;
+            let token = state
+                .signer
+                .sign_for(Purpose::PasswordReset, &user.username, lifetime);
+            let token = try_err!(token, Unknown);
+            notify(
+                &state,
+                Notification::PasswordReset {
+                    username: user.username,
+                    token,
+                    expires_in: lifetime,
+                },
+            );
+        }
+        _ => debug!("no password reset for {}", input.username),
+    }
+    Ok(output::RequestPasswordResetOutput {})
+}
+
+pub async fn confirm_password_reset(
+    input: input::ConfirmPasswordResetInput,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Result<output::ConfirmPasswordResetOutput, error::ConfirmPasswordResetError> {
+    let reset = state
+        .verifier
+        .verify_for(Purpose::PasswordReset, &input.token)?;
+    state.verifier.consume(&reset)?;
+    let username = reset.username;
+    info!("confirm password reset: {}", username);
+    let mut user = match try_err!(state.users.get(&username), Database) {
+        Some(user) if !user.disabled => user,
+        _ => unauthorized!("user {} is disabled or removed", username),
+    };
+    user.password_hash = try_err!(hash_password(input.new_password.as_str()).await, Unknown);
+    try_err!(state.users.update(user), Database);
+    try_err!(end_other_sessions(&state, &username, None), Database);
+    notify(&state, Notification::PasswordChanged { username });
+    Ok(output::ConfirmPasswordResetOutput {})
+}
+
+pub async fn enroll_mfa(
+    _input: input::EnrollMfaInput,
+    Extension(state): Extension<Arc<AppState>>,
+    identity: Identity,
+) -> Result<output::EnrollMfaOutput, error::EnrollMfaError> {
+    let username = &identity.username;
+    info!("enroll mfa: {}", username);
+    let Some(mut user) = try_err!(state.users.get(username), Database) else {
+        unauthorized!("user {} does not exist", username);
+    };
+    if user.mfa.as_ref().map_or(false, |mfa| mfa.enabled) {
+        conflict!("mfa of {} is already enabled", username);
+    }
+    let config = &state.config.auth.mfa;
+    let issuer = config
+        .issuer
+        .as_deref()
+        .unwrap_or(state.verifier.provider());
+    let enrollment = config.enroll(issuer, username)?;
+    user.mfa = Some(enrollment.mfa);
+    try_err!(state.users.update(user), Database);
+    Ok(output::EnrollMfaOutput {
+        secret: enrollment.secret,
+        otpauth_url: enrollment.url,
+        recovery_codes: enrollment.recovery_codes,
+    })
+}
+
+pub async fn confirm_mfa(
+    input: input::ConfirmMfaInput,
+    Extension(state): Extension<Arc<AppState>>,
+    identity: Identity,
+    ClientIp(ip): ClientIp,
+) -> Result<output::ConfirmMfaOutput, error::ConfirmMfaError> {
+    enable_mfa(&state, &identity.username, &input.code, ip)?;
+    Ok(output::ConfirmMfaOutput {})
+}
+
+/// Enable the MFA enrollment of a user with a code of their authenticator. Wrong codes count as
+/// failed signins, so a stolen access token can't be used to guess them.
+fn enable_mfa(
+    state: &AppState,
+    username: &str,
+    code: &str,
+    ip: Option<IpAddr>,
+) -> Result<(), error::ConfirmMfaError> {
+    info!("confirm mfa: {} from {:?}", username, ip);
+    let attempts = &state.signin_attempts;
+    if let Some(retry_after) = attempts.locked(username, ip) {
+        throttled!(
+            retry_after,
+            "too many failed attempts, retry in {} seconds",
+            retry_after
+        );
+    }
+    let Some(mut user) = try_err!(state.users.get(username), Database) else {
+        unauthorized!("user {} does not exist", username);
+    };
+    let Some(mfa) = user.mfa.as_mut() else {
+        not_found!("no mfa enrollment of {}", username);
+    };
+    if mfa.enabled {
+        conflict!("mfa of {} is already enabled", username);
+    }
+    let config = &state.config.auth.mfa;
+    if !config.verify_totp(mfa, code, store::now())? {
+        attempts.failed(username, ip);
+        unauthorized!("invalid mfa code");
+    }
+    mfa.enabled = true;
+    try_err!(state.users.update(user), Database);
+    Ok(())
+}
+
+/// Deliver a notification, failures are logged so they don't tell whether a user exists.
+fn notify(state: &AppState, notification: Notification) {
+    if let Err(e) = state.notifier.notify(&notification) {
+        warn!("failed to deliver notification: {}", e);
+    }
+}
+
+/// Claims of an access token for the user in a session, with the scopes granted by their roles.
+fn claims_of(state: &AppState, user: &User, session_id: &str) -> CustomClaims {
+    CustomClaims {
+        data: user.username.clone(),
+        roles: user.roles.clone(),
+        scopes: state.config.auth.roles.scopes(&user.roles),
+        session_id: Some(session_id.to_string()),
+        extra: Default::default(),
+    }
+}
+
+fn summary_of(key: ApiKey) -> model::ApiKeySummary {
+    model::ApiKeySummary {
+        id: key.id,
+        name: key.name,
+        scopes: key.scopes,
+        created_at: key.created_at as i64,
+        expires_at: key.expires_at.map(|t| t as i64),
+        last_used_at: key.last_used_at.map(|t| t as i64),
+    }
+}
+
+fn session_summary_of(session: Session, current: Option<&str>) -> model::SessionSummary {
+    model::SessionSummary {
+        current: current == Some(session.id.as_str()),
+        id: session.id,
+        user_agent: session.user_agent,
+        ip: session.ip,
+        created_at: session.created_at as i64,
+        last_seen_at: session.last_seen_at as i64,
+        expires_at: session.expires_at as i64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AppConfig;
+
+    #[test]
+    fn wrong_mfa_codes_should_be_throttled() {
+        let state = AppState::try_new(AppConfig::default()).unwrap();
+        let enrollment = state.config.auth.mfa.enroll("test", "alice").unwrap();
+        let user = User {
+            username: "alice".to_string(),
+            password_hash: "hash".to_string(),
+            roles: vec![],
+            disabled: false,
+            created_at: 0,
+            mfa: Some(enrollment.mfa),
+        };
+        state.users.insert(user).unwrap();
+
+        let ip = "10.0.0.1".parse().ok();
+        let free_attempts = state.config.auth.throttle.free_attempts;
+        for _ in 0..free_attempts {
+            assert!(matches!(
+                enable_mfa(&state, "alice", "wrong", ip),
+                Err(error::ConfirmMfaError::UnauthorizedError(_))
+            ));
+        }
+        assert!(matches!(
+            enable_mfa(&state, "alice", "wrong", ip),
+            Err(error::ConfirmMfaError::ThrottlingError(_))
+        ));
     }
-    let token = signer.sign(username)?;
-    Ok(output::SigninOutput { token })
 }
@@ -0,0 +1,482 @@
+//! Tamper-evident audit log of security events, like signins and denied requests.
+//!
+//! Every entry carries the HMAC of the previous one, keyed with a secret kept out of the log, so
+//! an entry deleted or edited in the middle of the log breaks the chain, see [`verify_chain`].
+//! Without the key the later entries can't be recomputed to hide the change.
+
+use crate::{middleware::ClientIp, store, AppState};
+use aws_smithy_http_server::request::{request_id::ServerRequestId, FromParts};
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{request::Parts, Extensions, HeaderMap, Request},
+};
+use derive_more::Debug;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{
+    convert::Infallible,
+    fmt::Display,
+    fs::{self, OpenOptions},
+    io::Write,
+    net::IpAddr,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+use thiserror::Error;
+use tracing::warn;
+
+/// `prev_hash` of the first entry.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Debug, Error)]
+pub enum AuditError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("serde error: {0}")]
+    Serde(#[from] serde_json::Error),
+    #[error("audit chain is broken at entry {0}")]
+    ChainBroken(u64),
+    #[error("audit log has no key, set key or key_env")]
+    MissingKey,
+    #[error("environment variable {0} of the audit key is not set")]
+    MissingKeyEnv(String),
+    #[error("audit log is unwritable since a failed write: {0}")]
+    Unwritable(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEvent {
+    Signin,
+    TokenRefresh,
+    Signout,
+    SessionRevoked,
+    ApiKeyRevoked,
+    /// token of the OAuth2 client credentials grant
+    ClientToken,
+    /// credentials rejected by the auth middleware
+    AuthenticationDenied,
+    /// caller without the scopes of the operation
+    AuthorizationDenied,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Success,
+    Failure,
+}
+
+/// A security event, before it is chained.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub event: AuditEvent,
+    pub outcome: Outcome,
+    /// username of the caller, if known
+    pub principal: Option<String>,
+    pub ip: Option<IpAddr>,
+    /// `x-request-id` of the request
+    pub request_id: Option<String>,
+    /// reason of a failure, or details of the event
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// An entry of the audit log.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// position in the log, starting at 1
+    pub seq: u64,
+    /// unix timestamp in seconds
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub record: AuditRecord,
+    pub prev_hash: String,
+    /// hex encoded HMAC-SHA256 of the entry without `hash`
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub hash: String,
+}
+
+/// Where audit entries are written.
+pub trait AuditSink: std::fmt::Debug + Send + Sync {
+    fn record(&self, record: AuditRecord) -> Result<AuditEntry, AuditError>;
+}
+
+/// Keeps the entries in memory, e.g. for tests.
+#[derive(Debug)]
+pub struct MemoryAuditSink {
+    #[debug(skip)]
+    key: Vec<u8>,
+    entries: Mutex<Vec<AuditEntry>>,
+}
+
+/// Appends the entries as json lines to a file, the chain continues across restarts.
+#[derive(Debug)]
+pub struct FileAuditSink {
+    path: PathBuf,
+    #[debug(skip)]
+    key: Vec<u8>,
+    /// the last entry written, or the error of a write that could not be undone
+    last: Mutex<Result<Option<AuditEntry>, String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AuditConfig {
+    /// entries are chained with a random key
+    Memory,
+    /// entries are chained with `key`, or the key in the environment variable `key_env`
+    File {
+        path: PathBuf,
+        #[debug(skip)]
+        #[serde(default)]
+        key: Option<String>,
+        #[serde(default)]
+        key_env: Option<String>,
+    },
+}
+
+/// Request id and client ip of a request, to put in its audit records.
+#[derive(Debug, Clone, Default)]
+pub struct AuditContext {
+    pub request_id: Option<String>,
+    pub ip: Option<IpAddr>,
+}
+
+impl AuditConfig {
+    pub fn sink(&self) -> Result<Arc<dyn AuditSink>, AuditError> {
+        Ok(match self {
+            Self::Memory => Arc::new(MemoryAuditSink::default()),
+            Self::File { path, key, key_env } => {
+                let key = match (key, key_env) {
+                    (Some(key), _) => key.clone(),
+                    (None, Some(name)) => {
+                        std::env::var(name).map_err(|_| AuditError::MissingKeyEnv(name.clone()))?
+                    }
+                    (None, None) => return Err(AuditError::MissingKey),
+                };
+                Arc::new(FileAuditSink::try_new(path.clone(), key.as_bytes())?)
+            }
+        })
+    }
+}
+
+impl AuditEntry {
+    /// Chain a record after `prev`, the last entry of the log.
+    fn chain(
+        record: AuditRecord,
+        prev: Option<&AuditEntry>,
+        key: &[u8],
+    ) -> Result<Self, AuditError> {
+        let mut entry = Self {
+            seq: prev.map_or(1, |p| p.seq + 1),
+            timestamp: store::now(),
+            record,
+            prev_hash: prev.map_or(GENESIS_HASH.to_string(), |p| p.hash.clone()),
+            hash: String::new(),
+        };
+        entry.hash = entry.digest(key)?;
+        Ok(entry)
+    }
+
+    fn digest(&self, key: &[u8]) -> Result<String, AuditError> {
+        let unhashed = Self {
+            hash: String::new(),
+            ..self.clone()
+        };
+        let json = serde_json::to_vec(&unhashed)?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac takes keys of any size");
+        mac.update(&json);
+        Ok(format!("{:x}", mac.finalize().into_bytes()))
+    }
+}
+
+/// Check with the key of the log that every entry is unchanged and follows the previous one.
+///
+/// Entries removed from the end of the log can only be detected by comparing the last `seq`
+/// with a copy kept elsewhere.
+pub fn verify_chain(entries: &[AuditEntry], key: &[u8]) -> Result<(), AuditError> {
+    let mut prev: Option<&AuditEntry> = None;
+    for entry in entries {
+        let prev_hash = prev.map_or(GENESIS_HASH, |p| p.hash.as_str());
+        let seq = prev.map_or(1, |p| p.seq + 1);
+        if entry.seq != seq || entry.prev_hash != prev_hash || entry.hash != entry.digest(key)? {
+            return Err(AuditError::ChainBroken(entry.seq));
+        }
+        prev = Some(entry);
+    }
+    Ok(())
+}
+
+impl MemoryAuditSink {
+    pub fn new(key: &[u8]) -> Self {
+        Self {
+            key: key.to_vec(),
+            entries: Mutex::default(),
+        }
+    }
+
+    pub fn entries(&self) -> Vec<AuditEntry> {
+        self.entries.lock().unwrap().clone()
+    }
+}
+
+impl AuditSink for MemoryAuditSink {
+    fn record(&self, record: AuditRecord) -> Result<AuditEntry, AuditError> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = AuditEntry::chain(record, entries.last(), &self.key)?;
+        entries.push(entry.clone());
+        Ok(entry)
+    }
+}
+
+impl Default for MemoryAuditSink {
+    /// A sink with a random key, its entries are gone with the process anyway.
+    fn default() -> Self {
+        let mut key = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut key);
+        Self::new(&key)
+    }
+}
+
+impl FileAuditSink {
+    /// Open the log, a partial last line left by a crash while writing is removed.
+    pub fn try_new(path: PathBuf, key: &[u8]) -> Result<Self, AuditError> {
+        // bytes, a partial line may end within a character
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e.into()),
+        };
+        // every complete entry ends with a newline
+        let complete = data.iter().rposition(|b| *b == b'\n').map_or(0, |i| i + 1);
+        if complete < data.len() {
+            warn!(
+                "removed partial audit entry at the end of {}",
+                path.display()
+            );
+            OpenOptions::new()
+                .write(true)
+                .open(&path)?
+                .set_len(complete as u64)?;
+        }
+        let last = match data[..complete].split(|b| *b == b'\n').rev().nth(1) {
+            Some(line) => Some(serde_json::from_slice(line)?),
+            None => None,
+        };
+        Ok(Self {
+            path,
+            key: key.to_vec(),
+            last: Mutex::new(Ok(last)),
+        })
+    }
+
+    /// Entries of the file, to check them with [`verify_chain`].
+    pub fn entries(&self) -> Result<Vec<AuditEntry>, AuditError> {
+        let data = fs::read_to_string(&self.path)?;
+        let entries = data
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?;
+        Ok(entries)
+    }
+}
+
+impl AuditSink for FileAuditSink {
+    fn record(&self, record: AuditRecord) -> Result<AuditEntry, AuditError> {
+        let mut last = self.last.lock().unwrap();
+        let prev = match &*last {
+            Ok(prev) => prev.as_ref(),
+            Err(e) => return Err(AuditError::Unwritable(e.clone())),
+        };
+        let entry = AuditEntry::chain(record, prev, &self.key)?;
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        let len = file.metadata()?.len();
+        if let Err(e) = file.write_all(&line) {
+            // a partial line in the middle of the log would break the chain for good
+            if let Err(truncate) = file.set_len(len) {
+                warn!("failed to remove a partial audit entry: {}", truncate);
+                *last = Err(e.to_string());
+            }
+            return Err(e.into());
+        }
+        *last = Ok(Some(entry.clone()));
+        Ok(entry)
+    }
+}
+
+impl AuditContext {
+    pub fn resolve(headers: &HeaderMap, extensions: &Extensions) -> Self {
+        Self {
+            request_id: extensions.get::<ServerRequestId>().map(ToString::to_string),
+            ip: ClientIp::resolve(headers, extensions).0,
+        }
+    }
+
+    /// A record of an event of the request with the outcome of `result`.
+    pub fn record<T, E: Display>(
+        &self,
+        event: AuditEvent,
+        principal: Option<&str>,
+        result: &Result<T, E>,
+    ) -> AuditRecord {
+        let (outcome, detail) = match result {
+            Ok(_) => (Outcome::Success, None),
+            Err(e) => (Outcome::Failure, Some(e.to_string())),
+        };
+        AuditRecord {
+            event,
+            outcome,
+            principal: principal.map(ToString::to_string),
+            ip: self.ip,
+            request_id: self.request_id.clone(),
+            detail,
+        }
+    }
+}
+
+/// Record a request denied by a middleware, if the audit log is enabled.
+pub(crate) fn record_denied<B>(
+    req: &Request<B>,
+    event: AuditEvent,
+    principal: Option<&str>,
+    reason: &dyn Display,
+) {
+    let Some(state) = req.extensions().get::<Arc<AppState>>() else {
+        return;
+    };
+    let context = AuditContext::resolve(req.headers(), req.extensions());
+    state.audit(context.record::<(), _>(event, principal, &Err(reason)));
+}
+
+impl AuditRecord {
+    /// Details of a successful event, a failure keeps its reason.
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        if self.outcome == Outcome::Success {
+            self.detail = Some(detail.into());
+        }
+        self
+    }
+}
+
+impl<P> FromParts<P> for AuditContext {
+    type Rejection = Infallible;
+
+    fn from_parts(parts: &mut Parts) -> Result<Self, Self::Rejection> {
+        Ok(Self::resolve(&parts.headers, &parts.extensions))
+    }
+}
+
+/// For the routes beside the Smithy service.
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for AuditContext {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self::resolve(&parts.headers, &parts.extensions))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"audit-key";
+
+    fn record(principal: &str) -> AuditRecord {
+        let context = AuditContext {
+            request_id: Some("req-1".to_string()),
+            ip: "10.0.0.1".parse().ok(),
+        };
+        context.record::<(), _>(
+            AuditEvent::Signin,
+            Some(principal),
+            &Err("invalid password"),
+        )
+    }
+
+    #[test]
+    fn deleted_entry_should_break_the_chain() {
+        let sink = MemoryAuditSink::new(KEY);
+        for principal in ["alice", "bob", "carol"] {
+            sink.record(record(principal)).unwrap();
+        }
+        let mut entries = sink.entries();
+        assert!(verify_chain(&entries, KEY).is_ok());
+        assert_eq!(entries[0].record.outcome, Outcome::Failure);
+
+        entries.remove(1);
+        assert!(matches!(
+            verify_chain(&entries, KEY),
+            Err(AuditError::ChainBroken(3))
+        ));
+        let mut entries = sink.entries();
+        entries[1].record.principal = Some("mallory".to_string());
+        assert!(matches!(
+            verify_chain(&entries, KEY),
+            Err(AuditError::ChainBroken(2))
+        ));
+    }
+
+    #[test]
+    fn file_sink_should_continue_the_chain() {
+        let path = std::env::temp_dir().join(format!("audit-{}.jsonl", uuid7::uuid7()));
+        FileAuditSink::try_new(path.clone(), KEY)
+            .unwrap()
+            .record(record("alice"))
+            .unwrap();
+        let sink = FileAuditSink::try_new(path.clone(), KEY).unwrap();
+        let entry = sink.record(record("bob")).unwrap();
+        assert_eq!(entry.seq, 2);
+
+        let entries = sink.entries().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(verify_chain(&entries, KEY).is_ok());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn file_sink_should_drop_a_partial_last_entry() {
+        let path = std::env::temp_dir().join(format!("audit-{}.jsonl", uuid7::uuid7()));
+        FileAuditSink::try_new(path.clone(), KEY)
+            .unwrap()
+            .record(record("alice"))
+            .unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"seq":2,"timest"#).unwrap();
+
+        let sink = FileAuditSink::try_new(path.clone(), KEY).unwrap();
+        assert_eq!(sink.record(record("bob")).unwrap().seq, 2);
+        let entries = sink.entries().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(verify_chain(&entries, KEY).is_ok());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rewritten_chain_should_not_verify_without_the_key() {
+        let sink = MemoryAuditSink::new(KEY);
+        for principal in ["alice", "bob"] {
+            sink.record(record(principal)).unwrap();
+        }
+        let mut entries = sink.entries();
+        // an attacker recomputes the chain after the edit, without the key
+        entries[0].record.principal = Some("mallory".to_string());
+        entries[0].hash = entries[0].digest(b"guessed").unwrap();
+        entries[1].prev_hash = entries[0].hash.clone();
+        entries[1].hash = entries[1].digest(b"guessed").unwrap();
+        assert!(verify_chain(&entries, b"guessed").is_ok());
+        assert!(matches!(
+            verify_chain(&entries, KEY),
+            Err(AuditError::ChainBroken(1))
+        ));
+    }
+}
@@ -0,0 +1,166 @@
+use super::{
+    refresh::{generate_token, hash_token, hashes_equal},
+    AuthError, Result, RoleConfig,
+};
+use crate::store::{self, ApiKey, ApiKeyStore, UserStore};
+use std::sync::Arc;
+
+/// Issues API keys for clients that can't signin interactively, like batch jobs.
+///
+/// A key is `<id>.<secret>`, the id locates the key in the store and only the hash of the secret is stored.
+///
+/// A key acts for its owner: it stops working once the owner is disabled or removed, and its
+/// scopes are limited to those the owner currently has.
+#[derive(Debug, Clone)]
+pub struct ApiKeys {
+    store: Arc<dyn ApiKeyStore>,
+    users: Arc<dyn UserStore>,
+    roles: RoleConfig,
+}
+
+impl ApiKeys {
+    pub fn new(store: Arc<dyn ApiKeyStore>, users: Arc<dyn UserStore>, roles: RoleConfig) -> Self {
+        Self {
+            store,
+            users,
+            roles,
+        }
+    }
+
+    /// Create a key acting for `owner`, returns the stored key and the key to hand to the client.
+    pub fn create(
+        &self,
+        owner: &str,
+        name: &str,
+        scopes: Vec<String>,
+        lifetime_seconds: Option<u64>,
+    ) -> Result<(ApiKey, String)> {
+        let id = uuid7::uuid7().to_string();
+        let secret = generate_token();
+        let now = store::now();
+        let key = ApiKey {
+            id: id.clone(),
+            hash: hash_token(&secret),
+            owner: owner.to_string(),
+            name: name.to_string(),
+            scopes,
+            created_at: now,
+            expires_at: lifetime_seconds.map(|s| now + s),
+            last_used_at: None,
+        };
+        self.store.insert(key.clone())?;
+        Ok((key, format!("{}.{}", id, secret)))
+    }
+
+    /// Verify a key presented by a client and record its use.
+    pub fn verify(&self, key: &str) -> Result<ApiKey> {
+        let (id, secret) = key.split_once('.').ok_or(AuthError::InvalidApiKey)?;
+        let Some(api_key) = self.store.get(id)? else {
+            return Err(AuthError::InvalidApiKey);
+        };
+        if !hashes_equal(&api_key.hash, &hash_token(secret)) {
+            return Err(AuthError::InvalidApiKey);
+        }
+        let now = store::now();
+        if api_key.expires_at.map_or(false, |t| t <= now) {
+            return Err(AuthError::ApiKeyExpired);
+        }
+        let owner = match self.users.get(&api_key.owner)? {
+            Some(owner) if !owner.disabled => owner,
+            _ => return Err(AuthError::InvalidApiKey),
+        };
+        let granted = self.roles.scopes(&owner.roles);
+        self.store.touch(id, now)?;
+        Ok(ApiKey {
+            scopes: api_key
+                .scopes
+                .into_iter()
+                .filter(|s| granted.contains(s))
+                .collect(),
+            last_used_at: Some(now),
+            ..api_key
+        })
+    }
+
+    pub fn list(&self, owner: &str) -> Result<Vec<ApiKey>> {
+        Ok(self.store.list(owner)?)
+    }
+
+    /// Revoke a key, only if it belongs to `owner`.
+    pub fn revoke(&self, id: &str, owner: &str) -> Result<()> {
+        match self.store.get(id)? {
+            Some(k) if k.owner == owner => Ok(self.store.remove(id)?),
+            _ => Err(AuthError::InvalidApiKey),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::{MemoryApiKeyStore, MemoryUserStore, User};
+
+    fn user(username: &str, roles: &[&str]) -> User {
+        User {
+            username: username.to_string(),
+            password_hash: "hash".to_string(),
+            roles: roles.iter().map(ToString::to_string).collect(),
+            disabled: false,
+            created_at: 0,
+            mfa: None,
+        }
+    }
+
+    fn api_keys() -> ApiKeys {
+        let users = Arc::new(MemoryUserStore::default());
+        users.insert(user("alice", &["user"])).unwrap();
+        ApiKeys::new(
+            Arc::new(MemoryApiKeyStore::default()),
+            users,
+            RoleConfig::default(),
+        )
+    }
+
+    #[test]
+    fn created_key_should_verify() {
+        let keys = api_keys();
+        let scopes = vec!["echo:write".to_string()];
+        let (created, key) = keys.create("alice", "batch", scopes, None).unwrap();
+        let verified = keys.verify(&key).unwrap();
+        assert_eq!(verified.id, created.id);
+        assert_eq!(verified.owner, "alice");
+        assert!(keys.list("alice").unwrap()[0].last_used_at.is_some());
+
+        let (id, _) = key.split_once('.').unwrap();
+        assert!(matches!(
+            keys.verify(&format!("{}.wrong", id)),
+            Err(AuthError::InvalidApiKey)
+        ));
+    }
+
+    #[test]
+    fn revoked_key_should_not_verify() {
+        let keys = api_keys();
+        let (created, key) = keys.create("alice", "batch", vec![], None).unwrap();
+        assert!(keys.revoke(&created.id, "bob").is_err());
+        keys.revoke(&created.id, "alice").unwrap();
+        assert!(matches!(keys.verify(&key), Err(AuthError::InvalidApiKey)));
+    }
+
+    #[test]
+    fn key_should_follow_its_owner() {
+        let keys = api_keys();
+        let scopes = vec!["echo:write".to_string(), "users:admin".to_string()];
+        let (_, key) = keys.create("alice", "batch", scopes, None).unwrap();
+        // scopes the owner lost, or never had, are not granted
+        assert_eq!(keys.verify(&key).unwrap().scopes, vec!["echo:write"]);
+
+        let mut alice = user("alice", &["user"]);
+        alice.disabled = true;
+        keys.users.update(alice).unwrap();
+        assert!(matches!(keys.verify(&key), Err(AuthError::InvalidApiKey)));
+
+        let (_, key) = keys.create("bob", "batch", vec![], None).unwrap();
+        assert!(matches!(keys.verify(&key), Err(AuthError::InvalidApiKey)));
+    }
+}
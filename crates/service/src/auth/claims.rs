@@ -0,0 +1,104 @@
+use super::CustomClaims;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+const DEFAULT_LIFETIME_SECONDS: u64 = 15 * 60;
+
+/// Claims owned by the signer, extra claims can't override them.
+const RESERVED_CLAIMS: &[&str] = &[
+    "iss", "sub", "aud", "exp", "nbf", "iat", "jti", "data", "roles", "scopes", "sid",
+];
+
+/// Content of the access tokens we issue.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenConfig {
+    /// lifetime of access tokens, clients may only request shorter ones
+    #[serde(default = "default_lifetime")]
+    pub lifetime_seconds: u64,
+    /// `iss` of the tokens, defaults to the server name
+    #[serde(default)]
+    pub issuer: Option<String>,
+    /// `aud` of the tokens
+    #[serde(default)]
+    pub audiences: Vec<String>,
+    /// claims added to every token
+    #[serde(default)]
+    pub extra_claims: BTreeMap<String, ClaimValue>,
+}
+
+/// Value of an extra claim, e.g. `{ "static": "acme" }` or `{ "dynamic": "username" }`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClaimValue {
+    /// the same value in every token
+    Static(Value),
+    /// a value of the identity the token is issued to
+    Dynamic(DynamicClaim),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DynamicClaim {
+    Username,
+    Roles,
+    Scopes,
+}
+
+impl TokenConfig {
+    /// Extra claims resolved for the token of `claims`, reserved claim names are skipped.
+    pub(super) fn extra_claims(&self, claims: &CustomClaims) -> BTreeMap<String, Value> {
+        self.extra_claims
+            .iter()
+            .filter(|(name, _)| !RESERVED_CLAIMS.contains(&name.as_str()))
+            .map(|(name, value)| (name.clone(), value.resolve(claims)))
+            .collect()
+    }
+}
+
+impl ClaimValue {
+    fn resolve(&self, claims: &CustomClaims) -> Value {
+        match self {
+            Self::Static(v) => v.clone(),
+            Self::Dynamic(DynamicClaim::Username) => Value::from(claims.data.as_str()),
+            Self::Dynamic(DynamicClaim::Roles) => Value::from(claims.roles.clone()),
+            Self::Dynamic(DynamicClaim::Scopes) => Value::from(claims.scopes.clone()),
+        }
+    }
+}
+
+impl Default for TokenConfig {
+    fn default() -> Self {
+        Self {
+            lifetime_seconds: DEFAULT_LIFETIME_SECONDS,
+            issuer: None,
+            audiences: vec![],
+            extra_claims: BTreeMap::new(),
+        }
+    }
+}
+
+fn default_lifetime() -> u64 {
+    DEFAULT_LIFETIME_SECONDS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extra_claims_should_resolve_and_skip_reserved_names() {
+        let config: TokenConfig = serde_json::from_value(serde_json::json!({
+            "extra_claims": {
+                "tenant": { "static": "acme" },
+                "name": { "dynamic": "username" },
+                "sub": { "static": "admin" }
+            }
+        }))
+        .unwrap();
+        let extra = config.extra_claims(&CustomClaims::new("alice"));
+        assert_eq!(extra.len(), 2);
+        assert_eq!(extra["tenant"], "acme");
+        assert_eq!(extra["name"], "alice");
+    }
+}
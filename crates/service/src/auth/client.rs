@@ -0,0 +1,88 @@
+use super::{
+    refresh::{hash_token, hashes_equal},
+    AuthConfig, AuthError, CustomClaims, Result,
+};
+use derive_more::Debug;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Prefix of the token subject of clients, usernames can't contain `:`.
+pub(crate) const CLIENT_PREFIX: &str = "client:";
+
+/// A registered OAuth2 client of the client credentials grant, e.g. an internal service.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientConfig {
+    pub id: String,
+    /// hex encoded sha256 of the secret, secrets must be random like API keys
+    #[debug(skip)]
+    pub secret_hash: String,
+    /// scopes the client may request
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+impl AuthConfig {
+    /// The registered client of a client id and secret.
+    pub fn client(&self, id: &str, secret: &str) -> Result<&ClientConfig> {
+        self.clients
+            .iter()
+            .find(|c| c.id == id && hashes_equal(&c.secret_hash, &hash_token(secret)))
+            .ok_or(AuthError::InvalidClient)
+    }
+}
+
+impl ClientConfig {
+    /// Scopes granted for the space separated `scope` of a request, all allowed scopes if absent.
+    pub fn grant(&self, requested: Option<&str>) -> Result<Vec<String>> {
+        let Some(requested) = requested else {
+            return Ok(self.scopes.clone());
+        };
+        requested
+            .split_whitespace()
+            .map(|scope| match self.scopes.iter().any(|s| s == scope) {
+                true => Ok(scope.to_string()),
+                false => Err(AuthError::ScopeNotAllowed(scope.to_string())),
+            })
+            .collect()
+    }
+
+    /// Claims of an access token of the client, its username can't be taken by a user.
+    pub fn claims(&self, scopes: Vec<String>) -> CustomClaims {
+        let mut claims = CustomClaims::new(format!("{}{}", CLIENT_PREFIX, self.id));
+        claims.scopes = scopes;
+        claims
+            .extra
+            .insert("client_id".to_string(), Value::from(self.id.as_str()));
+        claims
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_should_only_get_allowed_scopes() {
+        let config = AuthConfig {
+            clients: vec![ClientConfig {
+                id: "billing".to_string(),
+                secret_hash: hash_token("s3cret"),
+                scopes: vec!["echo:write".to_string(), "tokens:introspect".to_string()],
+            }],
+            ..AuthConfig::default()
+        };
+        assert!(config.client("billing", "wrong").is_err());
+        let client = config.client("billing", "s3cret").unwrap();
+
+        assert_eq!(client.grant(None).unwrap().len(), 2);
+        assert_eq!(
+            client.grant(Some("echo:write")).unwrap(),
+            vec!["echo:write"]
+        );
+        assert!(matches!(
+            client.grant(Some("echo:write admin")),
+            Err(AuthError::ScopeNotAllowed(_))
+        ));
+        assert_eq!(client.claims(vec![]).data, "client:billing");
+    }
+}
@@ -0,0 +1,327 @@
+use super::{
+    client::CLIENT_PREFIX, AuthError, Identity, Jwks, Result, RoleConfig, ValidationConfig,
+    VerifyingKey,
+};
+use crate::{store, tls::CERT_PREFIX};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use derive_more::Debug;
+use jwt_simple::prelude::*;
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        RwLock,
+    },
+};
+use tracing::{info, warn};
+
+const FETCH_TIMEOUT_SECONDS: u64 = 10;
+/// Prefixes of the usernames of other principals, an issuer can't take them over.
+const RESERVED_PREFIXES: [&str; 2] = [CERT_PREFIX, CLIENT_PREFIX];
+
+/// An external OpenID Connect provider whose tokens are trusted, e.g. a corporate IdP.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExternalIssuerConfig {
+    /// prefix of the usernames of the issuer, e.g. `corp` for `corp:1234`, `cert` and `client`
+    /// are reserved
+    pub name: String,
+    /// `iss` of the tokens of the issuer
+    pub issuer: String,
+    /// url of the JWKS of the issuer
+    pub jwks_uri: String,
+    /// audience that must be present in `aud`, e.g. the client id registered with the issuer,
+    /// so tokens the issuer gave to other applications are rejected
+    pub audience: String,
+    /// claim of the username
+    #[serde(default = "default_username_claim")]
+    pub username_claim: String,
+    /// claim of the groups of the user, a list or a single string
+    #[serde(default = "default_groups_claim")]
+    pub groups_claim: String,
+    /// roles granted to the members of each group
+    #[serde(default)]
+    pub group_roles: HashMap<String, Vec<String>>,
+    /// roles granted to every user of the issuer
+    #[serde(default)]
+    pub default_roles: Vec<String>,
+    /// minimum interval between fetches of the JWKS, a token with an unknown `kid` refetches it
+    #[serde(default = "default_min_refresh")]
+    pub min_refresh_seconds: u64,
+}
+
+/// Keys and claim mapping of an external issuer.
+///
+/// Keys are fetched on the first token of the issuer, and again when a token is signed by a
+/// key that is not known yet, at most once per `min_refresh_seconds`.
+#[derive(Debug)]
+pub struct ExternalIssuer {
+    config: ExternalIssuerConfig,
+    roles: RoleConfig,
+    #[debug(skip)]
+    options: VerificationOptions,
+    keys: RwLock<HashMap<String, VerifyingKey>>,
+    /// unix timestamp of the last fetch of the keys
+    fetched_at: AtomicU64,
+    #[debug(skip)]
+    client: reqwest::Client,
+}
+
+/// Claims of an external token, their names depend on the issuer.
+type ExternalClaims = BTreeMap<String, Value>;
+
+#[derive(Deserialize)]
+struct UnverifiedIssuer {
+    iss: Option<String>,
+}
+
+impl ExternalIssuer {
+    pub fn new(
+        config: ExternalIssuerConfig,
+        roles: RoleConfig,
+        validation: &ValidationConfig,
+    ) -> Result<Self> {
+        let prefix = format!("{}:", config.name);
+        if config.name.is_empty() || RESERVED_PREFIXES.iter().any(|p| prefix.starts_with(p)) {
+            return Err(AuthError::ReservedIssuerName(config.name));
+        }
+        let client = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(FETCH_TIMEOUT_SECONDS))
+            .build()
+            .map_err(|e| AuthError::JwksFetch(e.to_string()))?;
+        Ok(Self {
+            config,
+            roles,
+            options: validation.options(),
+            keys: RwLock::new(HashMap::new()),
+            fetched_at: AtomicU64::new(0),
+            client,
+        })
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.config.issuer
+    }
+
+    /// Whether the keys should be fetched to verify a token signed by `kid`.
+    pub fn needs_refresh(&self, kid: &str) -> bool {
+        let fetched_at = self.fetched_at.load(Ordering::Relaxed);
+        !self.keys.read().unwrap().contains_key(kid)
+            && store::now() >= fetched_at + self.config.min_refresh_seconds
+    }
+
+    /// Fetch the keys of the issuer, keys that can't be used are skipped.
+    pub async fn refresh(&self) -> Result<()> {
+        // concurrent requests with the unknown kid don't fetch again
+        self.fetched_at.store(store::now(), Ordering::Relaxed);
+        let jwks: Jwks = self
+            .client
+            .get(&self.config.jwks_uri)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| AuthError::JwksFetch(e.to_string()))?
+            .json()
+            .await
+            .map_err(|e| AuthError::JwksFetch(e.to_string()))?;
+        let keys: HashMap<_, _> = jwks
+            .keys
+            .iter()
+            .filter_map(|jwk| match VerifyingKey::from_jwk(jwk) {
+                Ok(key) => Some((jwk.kid.clone(), key)),
+                Err(e) => {
+                    warn!("skipped key of {}: {}", self.config.issuer, e);
+                    None
+                }
+            })
+            .collect();
+        info!("fetched {} keys of {}", keys.len(), self.config.issuer);
+        *self.keys.write().unwrap() = keys;
+        Ok(())
+    }
+
+    /// Verify a token of the issuer, and map its claims to an identity.
+    pub fn verify(&self, token: &str) -> Result<Identity> {
+        let metadata = Token::decode_metadata(token)?;
+        let kid = metadata.key_id().ok_or(AuthError::MissingKeyId)?;
+        let claims = {
+            let keys = self.keys.read().unwrap();
+            let key = keys
+                .get(kid)
+                .ok_or_else(|| AuthError::UnknownKeyId(kid.to_string()))?;
+            key.verify::<ExternalClaims>(token, self.options.clone())
+                .map_err(super::expired)?
+        };
+
+        let iss = claims.issuer.as_deref().unwrap_or_default();
+        if iss != self.config.issuer {
+            return Err(AuthError::IssuerNotAllowed(iss.to_string()));
+        }
+        let mut audiences: Vec<_> = claims
+            .audiences
+            .clone()
+            .map(|a| a.into_set().into_iter().collect())
+            .unwrap_or_default();
+        audiences.sort();
+        if !audiences.contains(&self.config.audience) {
+            return Err(AuthError::AudienceMismatch(self.config.audience.clone()));
+        }
+
+        let username_claim = &self.config.username_claim;
+        let username = match username_claim.as_str() {
+            "sub" => claims.subject.clone(),
+            claim => claims.custom.get(claim).and_then(string),
+        }
+        .ok_or_else(|| AuthError::MissingClaim(username_claim.clone()))?;
+        let groups = match claims.custom.get(&self.config.groups_claim) {
+            Some(Value::Array(groups)) => groups.iter().filter_map(string).collect(),
+            Some(group) => string(group).into_iter().collect(),
+            None => vec![],
+        };
+        let roles = self.roles(&groups);
+        Ok(Identity {
+            username: format!("{}:{}", self.config.name, username),
+            scopes: self.roles.scopes(&roles),
+            roles,
+            groups,
+            issuer: claims.issuer,
+            subject: claims.subject,
+            audiences,
+            jwt_id: claims.jwt_id,
+            issued_at: claims.issued_at.map(|t| t.as_secs()),
+            expires_at: claims.expires_at.map(|t| t.as_secs()),
+            not_before: claims.invalid_before.map(|t| t.as_secs()),
+            ..Default::default()
+        })
+    }
+
+    /// Sorted roles of the default roles and the roles of `groups`.
+    fn roles(&self, groups: &[String]) -> Vec<String> {
+        let roles: BTreeSet<_> = groups
+            .iter()
+            .filter_map(|g| self.config.group_roles.get(g))
+            .flatten()
+            .chain(&self.config.default_roles)
+            .cloned()
+            .collect();
+        roles.into_iter().collect()
+    }
+}
+
+/// `iss` of a token before its signature is verified, to select the keys that verify it.
+pub(super) fn unverified_issuer(token: &str) -> Option<String> {
+    let payload = token.split('.').nth(1)?;
+    let payload = URL_SAFE_NO_PAD.decode(payload).ok()?;
+    serde_json::from_slice::<UnverifiedIssuer>(&payload)
+        .ok()?
+        .iss
+}
+
+fn string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+fn default_username_claim() -> String {
+    "sub".to_string()
+}
+
+fn default_groups_claim() -> String {
+    "groups".to_string()
+}
+
+fn default_min_refresh() -> u64 {
+    60
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{Algorithm, SigningKey};
+    use axum::{routing::get, Json, Router};
+    use std::net::SocketAddr;
+
+    #[tokio::test]
+    async fn external_token_should_verify_with_fetched_keys() {
+        let sk = SigningKey::generate(Algorithm::EdDsa)
+            .unwrap()
+            .with_key_id("idp-1");
+        let jwks = Jwks {
+            keys: vec![sk.verifying_key().jwk("idp-1").unwrap()],
+        };
+        let app = Router::new().route("/jwks", get(move || async move { Json(jwks) }));
+        // bound before spawning so the keys are served by the time they are fetched
+        let listener = std::net::TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service());
+        tokio::spawn(async move { server.await.unwrap() });
+
+        let config = ExternalIssuerConfig {
+            name: "corp".to_string(),
+            issuer: "https://idp.example.com".to_string(),
+            jwks_uri: format!("http://{}/jwks", addr),
+            audience: "echo".to_string(),
+            username_claim: default_username_claim(),
+            groups_claim: default_groups_claim(),
+            group_roles: HashMap::from([("admins".to_string(), vec!["admin".to_string()])]),
+            default_roles: vec!["user".to_string()],
+            min_refresh_seconds: 60,
+        };
+        let issuer =
+            ExternalIssuer::new(config, RoleConfig::default(), &Default::default()).unwrap();
+        let custom =
+            ExternalClaims::from([("groups".to_string(), serde_json::json!(["admins", "staff"]))]);
+        let claims = Claims::with_custom_claims(custom, Duration::from_secs(60))
+            .with_issuer("https://idp.example.com")
+            .with_subject("1234")
+            .with_audience("echo");
+        let token = sk.sign(claims).unwrap();
+        assert_eq!(
+            unverified_issuer(&token).as_deref(),
+            Some("https://idp.example.com")
+        );
+
+        assert!(issuer.needs_refresh("idp-1"));
+        assert!(matches!(
+            issuer.verify(&token),
+            Err(AuthError::UnknownKeyId(_))
+        ));
+        issuer.refresh().await.unwrap();
+        let identity = issuer.verify(&token).unwrap();
+        assert_eq!(identity.username, "corp:1234");
+        assert_eq!(identity.groups, vec!["admins", "staff"]);
+        assert_eq!(identity.roles, vec!["admin", "user"]);
+        assert_eq!(identity.scopes, vec!["echo:write"]);
+        // unknown keys don't refetch more than once per interval
+        assert!(!issuer.needs_refresh("idp-2"));
+    }
+
+    #[test]
+    fn issuer_should_not_take_reserved_prefixes() {
+        let config = |name: &str| ExternalIssuerConfig {
+            name: name.to_string(),
+            issuer: "https://idp.example.com".to_string(),
+            jwks_uri: "https://idp.example.com/jwks".to_string(),
+            audience: "echo".to_string(),
+            username_claim: default_username_claim(),
+            groups_claim: default_groups_claim(),
+            group_roles: HashMap::new(),
+            default_roles: vec![],
+            min_refresh_seconds: 60,
+        };
+        for name in ["cert", "client", "cert:corp", ""] {
+            assert!(matches!(
+                ExternalIssuer::new(config(name), RoleConfig::default(), &Default::default()),
+                Err(AuthError::ReservedIssuerName(_))
+            ));
+        }
+        assert!(
+            ExternalIssuer::new(config("corp"), RoleConfig::default(), &Default::default()).is_ok()
+        );
+    }
+}
@@ -0,0 +1,89 @@
+use super::CustomClaims;
+use crate::middleware::AuthenticationError;
+use crate::store::ApiKey;
+use aws_smithy_http_server::request::FromParts;
+use axum::http::request::Parts;
+use jwt_simple::prelude::*;
+
+/// The authenticated caller of an operation.
+///
+/// Take `Identity` as a handler argument for operations that require auth,
+/// or `Option<Identity>` for anonymous operations where a caller may still present a token.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Identity {
+    pub username: String,
+    pub roles: Vec<String>,
+    pub scopes: Vec<String>,
+    /// groups of the user at an external issuer
+    pub groups: Vec<String>,
+    pub issuer: Option<String>,
+    pub subject: Option<String>,
+    pub audiences: Vec<String>,
+    pub jwt_id: Option<String>,
+    /// session of the signin the token was issued to
+    pub session_id: Option<String>,
+    /// id of the API key the caller authenticated with
+    pub api_key_id: Option<String>,
+    /// subject of the client certificate the caller authenticated with
+    pub certificate_subject: Option<String>,
+    /// unix timestamps in seconds
+    pub issued_at: Option<u64>,
+    pub expires_at: Option<u64>,
+    pub not_before: Option<u64>,
+}
+
+impl From<JWTClaims<CustomClaims>> for Identity {
+    fn from(claims: JWTClaims<CustomClaims>) -> Self {
+        let mut audiences: Vec<_> = claims
+            .audiences
+            .map(|a| a.into_set().into_iter().collect())
+            .unwrap_or_default();
+        audiences.sort();
+        Self {
+            username: claims.custom.data,
+            roles: claims.custom.roles,
+            scopes: claims.custom.scopes,
+            issuer: claims.issuer,
+            subject: claims.subject,
+            audiences,
+            jwt_id: claims.jwt_id,
+            session_id: claims.custom.session_id,
+            issued_at: claims.issued_at.map(|t| t.as_secs()),
+            expires_at: claims.expires_at.map(|t| t.as_secs()),
+            not_before: claims.invalid_before.map(|t| t.as_secs()),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<ApiKey> for Identity {
+    fn from(key: ApiKey) -> Self {
+        Self {
+            username: key.owner,
+            scopes: key.scopes,
+            api_key_id: Some(key.id),
+            issued_at: Some(key.created_at),
+            expires_at: key.expires_at,
+            ..Default::default()
+        }
+    }
+}
+
+impl Identity {
+    /// Whether the token grants every scope in `required`.
+    pub fn has_scopes(&self, required: &[String]) -> bool {
+        required.iter().all(|s| self.scopes.contains(s))
+    }
+}
+
+impl<P> FromParts<P> for Identity {
+    type Rejection = AuthenticationError;
+
+    fn from_parts(parts: &mut Parts) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Identity>()
+            .cloned()
+            .ok_or(AuthenticationError::Missing)
+    }
+}
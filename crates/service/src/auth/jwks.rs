@@ -0,0 +1,77 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+
+/// A JSON Web Key Set (RFC 7517) of the public keys that verify our tokens.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Jwk {
+    pub kty: String,
+    pub kid: String,
+    /// optional in the keys of external issuers
+    #[serde(default)]
+    pub alg: String,
+    #[serde(default, rename = "use")]
+    pub usage: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crv: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub y: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,
+}
+
+impl Jwk {
+    /// An Ed25519 public key as an octet key pair (RFC 8037).
+    pub fn ed25519(kid: impl Into<String>, pk: &[u8]) -> Self {
+        Self {
+            kty: "OKP".to_string(),
+            kid: kid.into(),
+            alg: "EdDSA".to_string(),
+            usage: "sig".to_string(),
+            crv: Some("Ed25519".to_string()),
+            x: Some(URL_SAFE_NO_PAD.encode(pk)),
+            y: None,
+            n: None,
+            e: None,
+        }
+    }
+
+    /// A P-256 public key from its uncompressed SEC1 encoding (`0x04 || x || y`).
+    pub fn p256(kid: impl Into<String>, pk: &[u8]) -> Self {
+        let (x, y) = pk[1..].split_at(32);
+        Self {
+            kty: "EC".to_string(),
+            kid: kid.into(),
+            alg: "ES256".to_string(),
+            usage: "sig".to_string(),
+            crv: Some("P-256".to_string()),
+            x: Some(URL_SAFE_NO_PAD.encode(x)),
+            y: Some(URL_SAFE_NO_PAD.encode(y)),
+            n: None,
+            e: None,
+        }
+    }
+
+    /// An RSA public key from its big endian modulus and exponent.
+    pub fn rsa(kid: impl Into<String>, n: &[u8], e: &[u8]) -> Self {
+        Self {
+            kty: "RSA".to_string(),
+            kid: kid.into(),
+            alg: "RS256".to_string(),
+            usage: "sig".to_string(),
+            crv: None,
+            x: None,
+            y: None,
+            n: Some(URL_SAFE_NO_PAD.encode(n)),
+            e: Some(URL_SAFE_NO_PAD.encode(e)),
+        }
+    }
+}
@@ -0,0 +1,432 @@
+use super::{AuthError, Jwk, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use derive_more::Debug;
+use jwt_simple::prelude::*;
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+const RSA_MODULUS_BITS: usize = 2048;
+
+/// JWT signing algorithms supported by the keyring.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Algorithm {
+    #[default]
+    #[serde(rename = "EdDSA")]
+    EdDsa,
+    #[serde(rename = "ES256")]
+    Es256,
+    #[serde(rename = "RS256")]
+    Rs256,
+    #[serde(rename = "HS256")]
+    Hs256,
+}
+
+/// A key in PEM format, `alg` defaults to the algorithm of [`AuthConfig`](super::AuthConfig).
+///
+/// Keys without `sk` are only used for verification, `pk` is derived from `sk` if absent and
+/// `kid` defaults to a fingerprint of the public key. For HS256, `sk` is the base64url encoded secret.
+///
+/// Instead of inline, `sk` and `pk` can be read from a file or an environment variable.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct KeyConfig {
+    #[serde(default)]
+    pub kid: Option<String>,
+    #[serde(default)]
+    pub alg: Option<Algorithm>,
+    #[debug(skip)]
+    #[serde(default)]
+    pub sk: Option<String>,
+    /// the shared secret too for HS256
+    #[debug(skip)]
+    #[serde(default)]
+    pub pk: Option<String>,
+    #[serde(default)]
+    pub sk_file: Option<PathBuf>,
+    #[serde(default)]
+    pub pk_file: Option<PathBuf>,
+    /// name of the environment variable holding the private key
+    #[serde(default)]
+    pub sk_env: Option<String>,
+    #[serde(default)]
+    pub pk_env: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum SigningKey {
+    EdDsa(#[debug(skip)] Ed25519KeyPair),
+    Es256(#[debug(skip)] ES256KeyPair),
+    Rs256(#[debug(skip)] RS256KeyPair),
+    Hs256(#[debug(skip)] HS256Key),
+}
+
+#[derive(Debug)]
+pub enum VerifyingKey {
+    EdDsa(#[debug(skip)] Ed25519PublicKey),
+    Es256(#[debug(skip)] ES256PublicKey),
+    Rs256(#[debug(skip)] RS256PublicKey),
+    Hs256(#[debug(skip)] HS256Key),
+}
+
+impl Algorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::EdDsa => "EdDSA",
+            Self::Es256 => "ES256",
+            Self::Rs256 => "RS256",
+            Self::Hs256 => "HS256",
+        }
+    }
+}
+
+impl KeyConfig {
+    pub fn generate(alg: Algorithm) -> Result<Self> {
+        let sk = SigningKey::generate(alg)?;
+        Ok(Self {
+            alg: Some(alg),
+            sk: Some(sk.to_pem()?),
+            ..Default::default()
+        })
+    }
+
+    /// Resolve key material from files and environment variables into `sk` and `pk`.
+    pub fn load(&self) -> Result<Self> {
+        Ok(Self {
+            kid: self.kid.clone(),
+            alg: self.alg,
+            sk: load_key(&self.sk, &self.sk_file, &self.sk_env)?,
+            pk: load_key(&self.pk, &self.pk_file, &self.pk_env)?,
+            ..Default::default()
+        })
+    }
+
+    /// Keys in a directory, sorted by kid.
+    ///
+    /// The file name without extension is the kid: `<kid>.pem` holds a private key, `<kid>.pub.pem`
+    /// a public key and `<kid>.key` a HS256 secret. Other files are ignored.
+    pub fn load_dir(dir: &Path, alg: Algorithm) -> Result<Vec<Self>> {
+        let mut keys: BTreeMap<String, Self> = BTreeMap::new();
+        let entries = fs::read_dir(dir).map_err(|e| AuthError::KeyFile(dir.to_path_buf(), e))?;
+        for entry in entries {
+            let path = entry
+                .map_err(|e| AuthError::KeyFile(dir.to_path_buf(), e))?
+                .path();
+            let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            let (kid, public, alg) = if let Some(kid) = name.strip_suffix(".pub.pem") {
+                (kid, true, alg)
+            } else if let Some(kid) = name.strip_suffix(".pem") {
+                (kid, false, alg)
+            } else if let Some(kid) = name.strip_suffix(".key") {
+                (kid, false, Algorithm::Hs256)
+            } else {
+                continue;
+            };
+
+            let content = read_key_file(&path)?;
+            let key = keys.entry(kid.to_string()).or_insert_with(|| Self {
+                kid: Some(kid.to_string()),
+                alg: Some(alg),
+                ..Default::default()
+            });
+            match public {
+                true => key.pk = Some(content),
+                false => key.sk = Some(content),
+            }
+        }
+        Ok(keys.into_values().collect())
+    }
+}
+
+impl SigningKey {
+    /// Write the key to a key directory as `<kid>.pem`, or `<kid>.key` for HS256, see
+    /// [`KeyConfig::load_dir`]. The file appears complete to concurrent readers.
+    pub fn save_to_dir(&self, dir: &Path, kid: &str) -> Result<PathBuf> {
+        let name = match self {
+            Self::Hs256(_) => format!("{}.key", kid),
+            _ => format!("{}.pem", kid),
+        };
+        let path = dir.join(name);
+        // ignored by `load_dir` until renamed
+        let tmp = dir.join(format!(".{}.tmp", kid));
+        write_private(&tmp, &self.to_pem()?)
+            .and_then(|_| fs::rename(&tmp, &path))
+            .map_err(|e| {
+                let _ = fs::remove_file(&tmp);
+                AuthError::KeyWrite(path.clone(), e)
+            })?;
+        Ok(path)
+    }
+
+    pub fn generate(alg: Algorithm) -> Result<Self> {
+        Ok(match alg {
+            Algorithm::EdDsa => Self::EdDsa(Ed25519KeyPair::generate()),
+            Algorithm::Es256 => Self::Es256(ES256KeyPair::generate()),
+            Algorithm::Rs256 => Self::Rs256(RS256KeyPair::generate(RSA_MODULUS_BITS)?),
+            Algorithm::Hs256 => Self::Hs256(HS256Key::generate()),
+        })
+    }
+
+    pub fn from_pem(alg: Algorithm, sk: &str) -> Result<Self> {
+        Ok(match alg {
+            Algorithm::EdDsa => Self::EdDsa(Ed25519KeyPair::from_pem(sk)?),
+            Algorithm::Es256 => Self::Es256(ES256KeyPair::from_pem(sk)?),
+            Algorithm::Rs256 => Self::Rs256(RS256KeyPair::from_pem(sk)?),
+            Algorithm::Hs256 => Self::Hs256(HS256Key::from_bytes(&decode_secret(sk)?)),
+        })
+    }
+
+    pub fn to_pem(&self) -> Result<String> {
+        Ok(match self {
+            // the pem of an ed25519 key pair also contains the public key
+            Self::EdDsa(k) => k
+                .to_pem()
+                .split("-----BEGIN PUBLIC KEY-----")
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+            Self::Es256(k) => k.to_pem()?,
+            Self::Rs256(k) => k.to_pem()?,
+            Self::Hs256(k) => URL_SAFE_NO_PAD.encode(k.to_bytes()),
+        })
+    }
+
+    pub fn with_key_id(self, kid: &str) -> Self {
+        match self {
+            Self::EdDsa(k) => Self::EdDsa(k.with_key_id(kid)),
+            Self::Es256(k) => Self::Es256(k.with_key_id(kid)),
+            Self::Rs256(k) => Self::Rs256(k.with_key_id(kid)),
+            Self::Hs256(k) => Self::Hs256(k.with_key_id(kid)),
+        }
+    }
+
+    pub fn verifying_key(&self) -> VerifyingKey {
+        match self {
+            Self::EdDsa(k) => VerifyingKey::EdDsa(k.public_key()),
+            Self::Es256(k) => VerifyingKey::Es256(k.public_key()),
+            Self::Rs256(k) => VerifyingKey::Rs256(k.public_key()),
+            Self::Hs256(k) => VerifyingKey::Hs256(k.clone()),
+        }
+    }
+
+    pub fn sign<C: Serialize + DeserializeOwned>(&self, claims: JWTClaims<C>) -> Result<String> {
+        Ok(match self {
+            Self::EdDsa(k) => k.sign(claims)?,
+            Self::Es256(k) => k.sign(claims)?,
+            Self::Rs256(k) => k.sign(claims)?,
+            Self::Hs256(k) => k.authenticate(claims)?,
+        })
+    }
+}
+
+impl VerifyingKey {
+    pub fn from_pem(alg: Algorithm, pk: &str) -> Result<Self> {
+        Ok(match alg {
+            Algorithm::EdDsa => Self::EdDsa(Ed25519PublicKey::from_pem(pk)?),
+            Algorithm::Es256 => Self::Es256(ES256PublicKey::from_pem(pk)?),
+            Algorithm::Rs256 => Self::Rs256(RS256PublicKey::from_pem(pk)?),
+            Algorithm::Hs256 => Self::Hs256(HS256Key::from_bytes(&decode_secret(pk)?)),
+        })
+    }
+
+    /// The public key of a JWK, e.g. of the JWKS of an external issuer.
+    pub fn from_jwk(jwk: &Jwk) -> Result<Self> {
+        let component = |value: &Option<String>, name: &str| match value {
+            Some(v) => decode_secret(v),
+            None => Err(AuthError::InvalidKey(format!(
+                "jwk {} has no {}",
+                jwk.kid, name
+            ))),
+        };
+        let key = match (jwk.kty.as_str(), jwk.crv.as_deref()) {
+            ("OKP", Some("Ed25519")) => {
+                Self::EdDsa(Ed25519PublicKey::from_bytes(&component(&jwk.x, "x")?)?)
+            }
+            ("EC", Some("P-256")) => {
+                let mut point = vec![0x04];
+                point.extend(component(&jwk.x, "x")?);
+                point.extend(component(&jwk.y, "y")?);
+                Self::Es256(ES256PublicKey::from_bytes(&point)?)
+            }
+            ("RSA", _) => Self::Rs256(RS256PublicKey::from_components(
+                &component(&jwk.n, "n")?,
+                &component(&jwk.e, "e")?,
+            )?),
+            (kty, crv) => {
+                return Err(AuthError::InvalidKey(format!(
+                    "jwk {} of type {} {:?} is not supported",
+                    jwk.kid, kty, crv
+                )))
+            }
+        };
+        Ok(key.with_key_id(&jwk.kid))
+    }
+
+    pub fn with_key_id(self, kid: &str) -> Self {
+        match self {
+            Self::EdDsa(k) => Self::EdDsa(k.with_key_id(kid)),
+            Self::Es256(k) => Self::Es256(k.with_key_id(kid)),
+            Self::Rs256(k) => Self::Rs256(k.with_key_id(kid)),
+            Self::Hs256(k) => Self::Hs256(k.with_key_id(kid)),
+        }
+    }
+
+    pub fn verify<C: Serialize + DeserializeOwned>(
+        &self,
+        token: &str,
+        options: VerificationOptions,
+    ) -> Result<JWTClaims<C>> {
+        let options = Some(options);
+        Ok(match self {
+            Self::EdDsa(k) => k.verify_token::<C>(token, options)?,
+            Self::Es256(k) => k.verify_token::<C>(token, options)?,
+            Self::Rs256(k) => k.verify_token::<C>(token, options)?,
+            Self::Hs256(k) => k.verify_token::<C>(token, options)?,
+        })
+    }
+
+    /// Fingerprint of the key, used as its default `kid`.
+    pub fn fingerprint(&self) -> Result<String> {
+        let bytes = match self {
+            Self::EdDsa(k) => k.to_bytes(),
+            Self::Es256(k) => k.to_bytes(),
+            Self::Rs256(k) => k.to_der()?,
+            Self::Hs256(k) => k.to_bytes(),
+        };
+        let hash = format!("{:x}", Sha256::digest(bytes));
+        Ok(hash[..16].to_string())
+    }
+
+    /// The key as a JWK, `None` for symmetric keys which must never be published.
+    pub fn jwk(&self, kid: &str) -> Option<Jwk> {
+        match self {
+            Self::EdDsa(k) => Some(Jwk::ed25519(kid, &k.to_bytes())),
+            Self::Es256(k) => Some(Jwk::p256(kid, &k.to_bytes_uncompressed())),
+            Self::Rs256(k) => {
+                let components = k.to_components();
+                Some(Jwk::rsa(kid, &components.n, &components.e))
+            }
+            Self::Hs256(_) => None,
+        }
+    }
+}
+
+/// Key material from the first configured source: inline, file or environment variable.
+/// Write a new file only readable by the owner, a stale file of a crash is replaced.
+fn write_private(path: &Path, content: &str) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    file.write_all(content.as_bytes())?;
+    file.sync_all()
+}
+
+fn load_key(
+    inline: &Option<String>,
+    file: &Option<PathBuf>,
+    env: &Option<String>,
+) -> Result<Option<String>> {
+    if inline.is_some() {
+        return Ok(inline.clone());
+    }
+    if let Some(path) = file {
+        return read_key_file(path).map(Some);
+    }
+    if let Some(name) = env {
+        let key = std::env::var(name).map_err(|_| AuthError::MissingKeyEnv(name.clone()))?;
+        // PEM in environment variables is often kept on a single line with escaped newlines
+        return Ok(Some(key.replace("\\n", "\n")));
+    }
+    Ok(None)
+}
+
+fn read_key_file(path: &Path) -> Result<String> {
+    fs::read_to_string(path).map_err(|e| AuthError::KeyFile(path.to_path_buf(), e))
+}
+
+fn decode_secret(secret: &str) -> Result<Vec<u8>> {
+    URL_SAFE_NO_PAD
+        .decode(secret.trim())
+        .map_err(|e| AuthError::InvalidKey(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_should_sign_and_verify_with_all_algorithms() {
+        for alg in [
+            Algorithm::EdDsa,
+            Algorithm::Es256,
+            Algorithm::Rs256,
+            Algorithm::Hs256,
+        ] {
+            let config = KeyConfig::generate(alg).unwrap();
+            let sk = SigningKey::from_pem(alg, config.sk.as_deref().unwrap()).unwrap();
+            let token = sk.sign(Claims::create(Duration::from_mins(1))).unwrap();
+            let claims = sk
+                .verifying_key()
+                .verify::<NoCustomClaims>(&token, Default::default());
+            assert!(claims.is_ok(), "{} failed to verify", alg.as_str());
+            assert_eq!(
+                sk.verifying_key().jwk("kid").is_none(),
+                alg == Algorithm::Hs256
+            );
+        }
+    }
+
+    #[test]
+    fn keys_should_load_from_dir() {
+        let dir = std::env::temp_dir().join(format!("keys-{}", uuid7::uuid7()));
+        fs::create_dir(&dir).unwrap();
+        let sk = KeyConfig::generate(Algorithm::EdDsa).unwrap().sk.unwrap();
+        let pk = Ed25519KeyPair::generate().public_key().to_pem();
+        fs::write(dir.join("2024-01.pem"), &sk).unwrap();
+        fs::write(dir.join("2023-12.pub.pem"), pk).unwrap();
+        fs::write(dir.join("README.md"), "ignored").unwrap();
+
+        let keys = KeyConfig::load_dir(&dir, Algorithm::EdDsa).unwrap();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].kid.as_deref(), Some("2023-12"));
+        assert!(keys[0].sk.is_none() && keys[0].pk.is_some());
+        assert_eq!(keys[1].kid.as_deref(), Some("2024-01"));
+        assert!(!format!("{:?}", keys[1]).contains(&sk));
+        let pk = keys[0].pk.as_deref().unwrap();
+        assert!(!format!("{:?}", keys[0]).contains(pk));
+
+        let config = KeyConfig {
+            sk_file: Some(dir.join("2024-01.pem")),
+            ..Default::default()
+        };
+        assert_eq!(config.load().unwrap().sk, Some(sk));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn saved_key_should_only_be_readable_by_the_owner() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("keys-{}", uuid7::uuid7()));
+        fs::create_dir(&dir).unwrap();
+        let sk = SigningKey::generate(Algorithm::Hs256).unwrap();
+        let path = sk.save_to_dir(&dir, "2024-01").unwrap();
+        assert_eq!(path, dir.join("2024-01.key"));
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
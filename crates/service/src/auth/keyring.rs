@@ -0,0 +1,468 @@
+use super::{Algorithm, AuthError, Jwks, KeyConfig, Result, SigningKey, VerifyingKey};
+use crate::store;
+use jwt_simple::prelude::*;
+use serde::de::DeserializeOwned;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+};
+use tracing::{info, warn};
+
+/// Interval to drop keys that are past their retirement time, and to load keys of other replicas.
+const RETIRE_CHECK_SECONDS: u64 = 60;
+/// Minimum interval between loads of the key directory for tokens with an unknown kid.
+const SYNC_MIN_SECONDS: u64 = 5;
+
+/// Keys used to sign and verify tokens, identified by the `kid` header of the token.
+///
+/// Only the active key signs. When rotated, the previous key keeps verifying tokens
+/// for `retire_after` seconds, long enough for the tokens it signed to expire.
+///
+/// With a key directory, rotated keys are written to it so they survive restarts, and keys
+/// rotated by other replicas sharing the directory are loaded. Rotated kids sort by creation
+/// time, the newest key signs.
+#[derive(Debug)]
+pub struct KeyRing {
+    keys: RwLock<Keys>,
+    /// algorithm of keys generated on rotation
+    algorithm: Algorithm,
+    retire_after: u64,
+    /// directory where rotated keys are kept, shared by the replicas
+    dir: Option<PathBuf>,
+    /// unix timestamp of the last load of `dir`
+    synced_at: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Keys {
+    active: String,
+    signing: SigningKey,
+    verifying: BTreeMap<String, KeyEntry>,
+    /// unix timestamp since the active key signs
+    active_since: u64,
+}
+
+#[derive(Debug)]
+struct KeyEntry {
+    key: VerifyingKey,
+    /// unix timestamp after which the key is dropped, `None` if it never retires
+    retire_at: Option<u64>,
+}
+
+impl KeyRing {
+    /// Build a keyring from configured keys, the signing key is `active` or the last key with a private key.
+    pub fn try_new(
+        keys: &[KeyConfig],
+        active: Option<&str>,
+        algorithm: Algorithm,
+        retire_after: u64,
+    ) -> Result<Self> {
+        let mut signing = None;
+        let mut verifying = BTreeMap::new();
+        for key in keys {
+            let (kid, sk, pk) = parse_key(key, algorithm)?;
+            if let Some(sk) = sk {
+                if active.map_or(true, |active| active == kid) {
+                    signing = Some((kid.clone(), sk));
+                }
+            }
+            let key = KeyEntry {
+                key: pk,
+                retire_at: None,
+            };
+            verifying.insert(kid, key);
+        }
+
+        let (active, signing) = signing.ok_or(AuthError::NoSigningKey)?;
+        Ok(Self {
+            keys: RwLock::new(Keys {
+                active,
+                signing,
+                verifying,
+                active_since: store::now(),
+            }),
+            algorithm,
+            retire_after,
+            dir: None,
+            synced_at: AtomicU64::new(0),
+        })
+    }
+
+    /// Keep rotated keys in `dir`, keys of the directory other than the active one retire.
+    pub fn with_dir(mut self, dir: PathBuf) -> Self {
+        let retire_at = store::now() + self.retire_after;
+        let keys = self.keys.get_mut().unwrap();
+        // the rotation clock of the active key survives restarts
+        if let Some(created_at) = created_at(&dir, &keys.active) {
+            keys.active_since = created_at;
+        }
+        for (kid, key) in keys.verifying.iter_mut() {
+            if *kid != keys.active && key_file(&dir, kid).is_some() {
+                key.retire_at = Some(retire_at);
+            }
+        }
+        self.dir = Some(dir);
+        self
+    }
+
+    pub fn sign<C: Serialize + DeserializeOwned>(&self, claims: JWTClaims<C>) -> Result<String> {
+        let keys = self.keys.read().unwrap();
+        Ok(keys.signing.sign(claims)?)
+    }
+
+    pub fn verify<C: Serialize + DeserializeOwned>(
+        &self,
+        token: &str,
+        options: VerificationOptions,
+    ) -> Result<JWTClaims<C>> {
+        let metadata = Token::decode_metadata(token)?;
+        let kid = metadata.key_id().ok_or(AuthError::MissingKeyId)?;
+        if self.needs_sync(kid) {
+            // the token may be signed by a key just rotated by another replica
+            if let Err(e) = self.sync() {
+                warn!("failed to load rotated keys: {}", e);
+            }
+        }
+        let keys = self.keys.read().unwrap();
+        let key = keys
+            .verifying
+            .get(kid)
+            .ok_or_else(|| AuthError::UnknownKeyId(kid.to_string()))?;
+        key.key.verify::<C>(token, options)
+    }
+
+    /// Public keys of the keyring, including the retiring ones.
+    pub fn jwks(&self) -> Jwks {
+        let keys = self.keys.read().unwrap();
+        let keys = keys
+            .verifying
+            .iter()
+            .filter_map(|(kid, key)| key.key.jwk(kid))
+            .collect();
+        Jwks { keys }
+    }
+
+    /// Generate a new signing key, the previous one retires after `retire_after` seconds.
+    pub fn rotate(&self) -> Result<String> {
+        let (kid, sk) = generate_key(self.algorithm, self.dir.as_deref())?;
+        self.activate(kid.clone(), sk, store::now());
+        info!("rotated signing key, active kid: {}", kid);
+        self.retire();
+        Ok(kid)
+    }
+
+    /// Load the keys of the key directory that are not known yet, e.g. rotated by another
+    /// replica. The newest one signs if it is newer than the active key, the others retire.
+    pub fn sync(&self) -> Result<()> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+        self.synced_at.store(store::now(), Ordering::Relaxed);
+        let configs = KeyConfig::load_dir(dir, self.algorithm)?;
+
+        let retire_at = store::now() + self.retire_after;
+        let mut newest = None;
+        let mut keys = self.keys.write().unwrap();
+        // sorted by kid, so by creation time
+        for config in &configs {
+            if config
+                .kid
+                .as_ref()
+                .map_or(true, |k| keys.verifying.contains_key(k))
+            {
+                continue;
+            }
+            let (kid, sk, pk) = match parse_key(config, self.algorithm) {
+                Ok(key) => key,
+                Err(e) => {
+                    warn!("skipped key {:?} of {}: {}", config.kid, dir.display(), e);
+                    continue;
+                }
+            };
+            info!("loaded signing key: {}", kid);
+            if let (Some(sk), true) = (sk, kid > keys.active) {
+                newest = Some((kid.clone(), sk));
+            }
+            let key = KeyEntry {
+                key: pk,
+                retire_at: Some(retire_at),
+            };
+            keys.verifying.insert(kid, key);
+        }
+        drop(keys);
+
+        if let Some((kid, sk)) = newest {
+            info!("signing with loaded key: {}", kid);
+            let since = created_at(dir, &kid).unwrap_or_else(store::now);
+            self.activate(kid, sk, since);
+        }
+        Ok(())
+    }
+
+    /// Drop keys that are past their retirement time, and their files in the key directory.
+    pub fn retire(&self) {
+        let now = store::now();
+        let mut keys = self.keys.write().unwrap();
+        keys.verifying.retain(|kid, key| match key.retire_at {
+            Some(retire_at) if retire_at <= now => {
+                info!("retired signing key: {}", kid);
+                if let Some(path) = self.dir.as_deref().and_then(|dir| key_file(dir, kid)) {
+                    // another replica may have removed it already
+                    if let Err(e) = fs::remove_file(&path) {
+                        warn!("failed to remove key {}: {}", path.display(), e);
+                    }
+                }
+                false
+            }
+            _ => true,
+        });
+    }
+
+    /// Rotate the signing key once it has signed for `interval`, in a background task that also
+    /// loads the keys of other replicas and retires old keys on time.
+    pub fn spawn_rotation(self: Arc<Self>, interval: std::time::Duration) {
+        if interval.is_zero() {
+            warn!("key rotation interval is zero, rotation disabled");
+            return;
+        }
+        tokio::spawn(async move {
+            let mut tick =
+                tokio::time::interval(std::time::Duration::from_secs(RETIRE_CHECK_SECONDS));
+            loop {
+                tick.tick().await;
+                // a key rotated by another replica is adopted instead of rotating again
+                if let Err(e) = self.sync() {
+                    warn!("failed to load rotated keys: {}", e);
+                }
+                let active_since = self.keys.read().unwrap().active_since;
+                if store::now() >= active_since + interval.as_secs() {
+                    if let Err(e) = self.rotate() {
+                        warn!("failed to rotate signing key: {}", e);
+                    }
+                }
+                self.retire();
+            }
+        });
+    }
+
+    /// Make a key created at `since` the signing key, the previous one retires after
+    /// `retire_after` seconds.
+    fn activate(&self, kid: String, sk: SigningKey, since: u64) {
+        let now = store::now();
+        let mut keys = self.keys.write().unwrap();
+        let active = keys.active.clone();
+        if let Some(key) = keys.verifying.get_mut(&active) {
+            key.retire_at = Some(now + self.retire_after);
+        }
+        let key = KeyEntry {
+            key: sk.verifying_key().with_key_id(&kid),
+            retire_at: None,
+        };
+        keys.verifying.insert(kid.clone(), key);
+        keys.signing = sk.with_key_id(&kid);
+        keys.active = kid;
+        keys.active_since = since;
+    }
+
+    /// Whether the key directory should be loaded to verify a token signed by `kid`.
+    fn needs_sync(&self, kid: &str) -> bool {
+        self.dir.is_some()
+            && !self.keys.read().unwrap().verifying.contains_key(kid)
+            && store::now() >= self.synced_at.load(Ordering::Relaxed) + SYNC_MIN_SECONDS
+    }
+}
+
+/// Generate a signing key with a kid that sorts by creation time, saved to `dir` if given.
+pub(super) fn generate_key(alg: Algorithm, dir: Option<&Path>) -> Result<(String, SigningKey)> {
+    let sk = SigningKey::generate(alg)?;
+    let kid = uuid7::uuid7().to_string();
+    if let Some(dir) = dir {
+        sk.save_to_dir(dir, &kid)?;
+    }
+    Ok((kid, sk))
+}
+
+/// Kid, private key with the kid and public key with the kid of a configured key.
+fn parse_key(
+    key: &KeyConfig,
+    algorithm: Algorithm,
+) -> Result<(String, Option<SigningKey>, VerifyingKey)> {
+    let alg = key.alg.unwrap_or(algorithm);
+    let sk = key
+        .sk
+        .as_deref()
+        .map(|sk| SigningKey::from_pem(alg, sk))
+        .transpose()?;
+    let pk = match (&key.pk, &sk) {
+        (Some(pk), _) => VerifyingKey::from_pem(alg, pk)?,
+        (None, Some(sk)) => sk.verifying_key(),
+        (None, None) => return Err(AuthError::InvalidKey("key has neither sk nor pk".into())),
+    };
+    let kid = match &key.kid {
+        Some(kid) => kid.clone(),
+        None => pk.fingerprint()?,
+    };
+    let sk = sk.map(|sk| sk.with_key_id(&kid));
+    let pk = pk.with_key_id(&kid);
+    Ok((kid, sk, pk))
+}
+
+/// File of a private key in a key directory, see [`KeyConfig::load_dir`].
+fn key_file(dir: &Path, kid: &str) -> Option<PathBuf> {
+    [format!("{}.pem", kid), format!("{}.key", kid)]
+        .into_iter()
+        .map(|name| dir.join(name))
+        .find(|path| path.exists())
+}
+
+/// Unix timestamp of the creation of a key of the key directory, from its uuid7 kid or else
+/// from the modification time of its file.
+fn created_at(dir: &Path, kid: &str) -> Option<u64> {
+    if let Ok(uuid) = kid.parse::<uuid7::Uuid>() {
+        let bytes = uuid.as_bytes();
+        // version 7, whose first 48 bits are the unix timestamp in milliseconds
+        if bytes[6] >> 4 == 7 {
+            let mut millis = [0u8; 8];
+            millis[2..].copy_from_slice(&bytes[..6]);
+            return Some(u64::from_be_bytes(millis) / 1000);
+        }
+    }
+    let modified = fs::metadata(key_file(dir, kid)?).ok()?.modified().ok()?;
+    let since_epoch = modified.duration_since(std::time::UNIX_EPOCH).ok()?;
+    Some(since_epoch.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims() -> JWTClaims<NoCustomClaims> {
+        Claims::create(Duration::from_mins(1))
+    }
+
+    fn keyring(retire_after: u64) -> KeyRing {
+        let keys = [KeyConfig::generate(Algorithm::EdDsa).unwrap()];
+        KeyRing::try_new(&keys, None, Algorithm::EdDsa, retire_after).unwrap()
+    }
+
+    #[test]
+    fn rotated_keyring_should_verify_tokens_of_previous_key() {
+        let keyring = keyring(60);
+        let token = keyring.sign(claims()).unwrap();
+        let kid = keyring.rotate().unwrap();
+        let new_token = keyring.sign(claims()).unwrap();
+
+        let metadata = Token::decode_metadata(&new_token).unwrap();
+        assert_eq!(metadata.key_id(), Some(kid.as_str()));
+        assert!(keyring
+            .verify::<NoCustomClaims>(&token, Default::default())
+            .is_ok());
+        assert!(keyring
+            .verify::<NoCustomClaims>(&new_token, Default::default())
+            .is_ok());
+    }
+
+    #[test]
+    fn jwks_should_list_all_verifying_keys() {
+        let keyring = keyring(60);
+        let kid = keyring.rotate().unwrap();
+        let jwks = keyring.jwks();
+        assert_eq!(jwks.keys.len(), 2);
+        let jwk = jwks.keys.iter().find(|k| k.kid == kid).unwrap();
+        assert_eq!(jwk.alg, "EdDSA");
+        assert_eq!(jwk.usage, "sig");
+    }
+
+    #[test]
+    fn retired_key_should_not_verify() {
+        let keyring = keyring(0);
+        let token = keyring.sign(claims()).unwrap();
+        keyring.rotate().unwrap();
+        assert!(matches!(
+            keyring.verify::<NoCustomClaims>(&token, Default::default()),
+            Err(AuthError::UnknownKeyId(_))
+        ));
+    }
+
+    #[test]
+    fn replicas_should_share_rotated_keys_through_the_directory() {
+        let dir = std::env::temp_dir().join(format!("keys-{}", uuid7::uuid7()));
+        fs::create_dir(&dir).unwrap();
+        let (kid, sk) = generate_key(Algorithm::EdDsa, Some(&dir)).unwrap();
+        assert!(key_file(&dir, &kid).is_some());
+        let replica = || {
+            let keys = KeyConfig::load_dir(&dir, Algorithm::EdDsa).unwrap();
+            KeyRing::try_new(&keys, None, Algorithm::EdDsa, 60)
+                .unwrap()
+                .with_dir(dir.clone())
+        };
+        let (first, second) = (replica(), replica());
+        assert!(first
+            .verify::<NoCustomClaims>(
+                &sk.with_key_id(&kid).sign(claims()).unwrap(),
+                Default::default()
+            )
+            .is_ok());
+
+        let rotated = first.rotate().unwrap();
+        assert!(rotated > kid);
+        let token = first.sign(claims()).unwrap();
+        // the unknown kid loads the directory
+        assert!(second
+            .verify::<NoCustomClaims>(&token, Default::default())
+            .is_ok());
+        let metadata = Token::decode_metadata(&second.sign(claims()).unwrap()).unwrap();
+        assert_eq!(metadata.key_id(), Some(rotated.as_str()));
+
+        // a restart signs with the newest key and still verifies the tokens of the others
+        let restarted = replica();
+        let metadata = Token::decode_metadata(&restarted.sign(claims()).unwrap()).unwrap();
+        assert_eq!(metadata.key_id(), Some(rotated.as_str()));
+        assert_eq!(restarted.jwks().keys.len(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn activation_time_should_come_from_the_key() {
+        let dir = std::env::temp_dir().join(format!("keys-{}", uuid7::uuid7()));
+        fs::create_dir(&dir).unwrap();
+        // a uuid7 kid carries its creation time
+        let kid = "00e8d4a5-1000-7000-8000-000000000000";
+        assert_eq!(created_at(&dir, kid), Some(1_000_000_000));
+        // other kids fall back to the modification time of their file
+        fs::write(dir.join("legacy.pem"), "").unwrap();
+        let created = created_at(&dir, "legacy").unwrap();
+        assert!(created.abs_diff(store::now()) <= 5);
+        assert_eq!(created_at(&dir, "missing"), None);
+        fs::remove_file(dir.join("legacy.pem")).unwrap();
+
+        // a restart keeps the activation time of the loaded key
+        let (kid, _) = generate_key(Algorithm::EdDsa, Some(&dir)).unwrap();
+        let keys = KeyConfig::load_dir(&dir, Algorithm::EdDsa).unwrap();
+        let keyring = KeyRing::try_new(&keys, None, Algorithm::EdDsa, 60)
+            .unwrap()
+            .with_dir(dir.clone());
+        let active_since = keyring.keys.read().unwrap().active_since;
+        assert_eq!(Some(active_since), created_at(&dir, &kid));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn keyring_should_sign_with_active_key() {
+        let mut first = KeyConfig::generate(Algorithm::Es256).unwrap();
+        first.kid = Some("first".to_string());
+        let second = KeyConfig::generate(Algorithm::EdDsa).unwrap();
+        let keys = [first, second];
+        let keyring = KeyRing::try_new(&keys, Some("first"), Algorithm::EdDsa, 60).unwrap();
+        let token = keyring.sign(claims()).unwrap();
+        let metadata = Token::decode_metadata(&token).unwrap();
+        assert_eq!(metadata.key_id(), Some("first"));
+        assert_eq!(metadata.algorithm(), "ES256");
+        assert_eq!(keyring.jwks().keys.len(), 2);
+    }
+}
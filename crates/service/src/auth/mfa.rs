@@ -0,0 +1,176 @@
+use super::{refresh::hash_token, AuthError, Result};
+use crate::store::UserMfa;
+use rand::{Rng, RngCore};
+use serde::{Deserialize, Serialize};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+const STEP_SECONDS: u64 = 30;
+const DIGITS: usize = 6;
+/// Recovery code characters, without look-alikes like `0` and `o`.
+const RECOVERY_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+/// TOTP (RFC 6238) multi-factor authentication at signin.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MfaConfig {
+    /// issuer shown by authenticator apps, defaults to the token issuer
+    #[serde(default)]
+    pub issuer: Option<String>,
+    /// lifetime of the challenge token between the password and the code
+    #[serde(default = "default_challenge_lifetime")]
+    pub challenge_lifetime_seconds: u64,
+    /// recovery codes generated at enrollment
+    #[serde(default = "default_recovery_codes")]
+    pub recovery_codes: usize,
+    /// codes accepted before and after the current one, for clock drift
+    #[serde(default = "default_skew_steps")]
+    pub skew_steps: u64,
+}
+
+/// A new TOTP authenticator, MFA is enabled once a code of it is confirmed.
+#[derive(Debug)]
+pub struct Enrollment {
+    /// base32 secret for authenticator apps
+    pub secret: String,
+    /// `otpauth://` url of the secret
+    pub url: String,
+    /// only their hashes are kept in [`UserMfa`]
+    pub recovery_codes: Vec<String>,
+    pub mfa: UserMfa,
+}
+
+impl MfaConfig {
+    pub fn enroll(&self, issuer: &str, username: &str) -> Result<Enrollment> {
+        let mut secret = [0u8; 20];
+        rand::thread_rng().fill_bytes(&mut secret);
+        let secret = Secret::Raw(secret.to_vec()).to_encoded().to_string();
+        let url = totp(&secret, Some(issuer), username)?.get_url();
+        let recovery_codes: Vec<_> = (0..self.recovery_codes).map(|_| recovery_code()).collect();
+        let mfa = UserMfa {
+            secret: secret.clone(),
+            enabled: false,
+            recovery_codes: recovery_codes
+                .iter()
+                .map(|c| hash_token(&normalize(c)))
+                .collect(),
+            last_step: 0,
+        };
+        Ok(Enrollment {
+            secret,
+            url,
+            recovery_codes,
+            mfa,
+        })
+    }
+
+    /// Check a TOTP code at unix time `now`, an accepted code can't be used again.
+    pub fn verify_totp(&self, mfa: &mut UserMfa, code: &str, now: u64) -> Result<bool> {
+        let totp = totp(&mfa.secret, None, "")?;
+        let current = now / STEP_SECONDS;
+        let step = (current.saturating_sub(self.skew_steps)..=current + self.skew_steps)
+            .filter(|step| *step > mfa.last_step)
+            .find(|step| totp.generate(step * STEP_SECONDS) == code.trim());
+        if let Some(step) = step {
+            mfa.last_step = step;
+        }
+        Ok(step.is_some())
+    }
+
+    /// Check a TOTP code, or a recovery code which is removed once used.
+    pub fn verify(&self, mfa: &mut UserMfa, code: &str, now: u64) -> Result<bool> {
+        if self.verify_totp(mfa, code, now)? {
+            return Ok(true);
+        }
+        let hash = hash_token(&normalize(code));
+        let unused = mfa.recovery_codes.len();
+        mfa.recovery_codes.retain(|h| *h != hash);
+        Ok(mfa.recovery_codes.len() < unused)
+    }
+}
+
+impl Default for MfaConfig {
+    fn default() -> Self {
+        Self {
+            issuer: None,
+            challenge_lifetime_seconds: default_challenge_lifetime(),
+            recovery_codes: default_recovery_codes(),
+            skew_steps: default_skew_steps(),
+        }
+    }
+}
+
+fn totp(secret: &str, issuer: Option<&str>, username: &str) -> Result<TOTP> {
+    let secret = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|e| AuthError::Totp(e.to_string()))?;
+    TOTP::new(
+        Algorithm::SHA1,
+        DIGITS,
+        0,
+        STEP_SECONDS,
+        secret,
+        issuer.map(ToString::to_string),
+        username.to_string(),
+    )
+    .map_err(|e| AuthError::Totp(e.to_string()))
+}
+
+/// A random recovery code like `k7m2p-x9qrt`.
+fn recovery_code() -> String {
+    let mut rng = rand::thread_rng();
+    let code: String = (0..10)
+        .map(|_| RECOVERY_ALPHABET[rng.gen_range(0..RECOVERY_ALPHABET.len())] as char)
+        .collect();
+    format!("{}-{}", &code[..5], &code[5..])
+}
+
+/// Recovery codes are compared without separators and case.
+fn normalize(code: &str) -> String {
+    code.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn default_challenge_lifetime() -> u64 {
+    5 * 60
+}
+
+fn default_recovery_codes() -> usize {
+    10
+}
+
+fn default_skew_steps() -> u64 {
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn totp_code_should_not_be_replayed() {
+        let config = MfaConfig::default();
+        let mut mfa = config.enroll("echo", "alice").unwrap().mfa;
+        let now = 1_700_000_000;
+        let code = totp(&mfa.secret, None, "").unwrap().generate(now);
+
+        assert!(config.verify_totp(&mut mfa, &code, now + 20).unwrap());
+        assert!(!config.verify_totp(&mut mfa, &code, now + 20).unwrap());
+        let expired = totp(&mfa.secret, None, "").unwrap().generate(now - 120);
+        assert!(!config.verify_totp(&mut mfa, &expired, now).unwrap());
+    }
+
+    #[test]
+    fn recovery_code_should_be_single_use() {
+        let config = MfaConfig::default();
+        let enrollment = config.enroll("echo", "alice").unwrap();
+        assert!(enrollment.url.starts_with("otpauth://totp/"));
+        assert_eq!(enrollment.recovery_codes.len(), 10);
+
+        let mut mfa = enrollment.mfa;
+        let code = enrollment.recovery_codes[0].to_uppercase();
+        assert!(config.verify(&mut mfa, &code, 0).unwrap());
+        assert!(!config.verify(&mut mfa, &code, 0).unwrap());
+        assert_eq!(mfa.recovery_codes.len(), 9);
+    }
+}
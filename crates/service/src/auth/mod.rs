@@ -0,0 +1,655 @@
+mod api_key;
+mod claims;
+mod client;
+mod external;
+mod identity;
+mod jwks;
+mod key;
+mod keyring;
+mod mfa;
+mod password;
+mod purpose;
+mod refresh;
+mod role;
+mod session;
+mod sessions;
+mod throttle;
+mod validation;
+
+pub use api_key::ApiKeys;
+pub use claims::{ClaimValue, DynamicClaim, TokenConfig};
+pub use client::ClientConfig;
+pub use external::{ExternalIssuer, ExternalIssuerConfig};
+pub use identity::Identity;
+pub use jwks::{Jwk, Jwks};
+pub use key::{Algorithm, KeyConfig, SigningKey, VerifyingKey};
+pub use keyring::KeyRing;
+pub use mfa::{Enrollment, MfaConfig};
+pub use password::{dummy_verify_password, hash_password, verify_password};
+pub use purpose::{Purpose, PurposeToken, ResetConfig};
+pub use refresh::RefreshTokens;
+pub use role::RoleConfig;
+pub use session::{SameSite, SessionConfig};
+pub use sessions::Sessions;
+pub use throttle::{AttemptTracker, ThrottleConfig};
+pub use validation::ValidationConfig;
+
+use crate::store::{self, RevocationStore, StoreError};
+use derive_more::Debug;
+use echo_server_sdk::error::{
+    ConfirmMfaError, ConfirmPasswordResetError, CreateApiKeyError, EnrollMfaError,
+    ListApiKeysError, ListSessionsError, NotFoundError, RefreshTokenError, RevokeApiKeyError,
+    RevokeSessionError, ServerError, SigninError, SignoutError, UnauthorizedError,
+};
+use echo_server_sdk::model::ErrorCode;
+use jwt_simple::{prelude::*, JWTError};
+use std::{
+    collections::{BTreeMap, HashSet},
+    sync::Arc,
+};
+use thiserror::Error;
+use tracing::error;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthConfig {
+    /// algorithm of generated keys, and of configured keys without `alg`
+    #[serde(default)]
+    pub algorithm: Algorithm,
+    #[serde(default)]
+    pub keys: Vec<KeyConfig>,
+    /// directory of PEM files loaded after `keys`, see [`KeyConfig::load_dir`]
+    #[serde(default)]
+    pub key_dir: Option<std::path::PathBuf>,
+    /// kid of the signing key, defaults to the last key with a private key
+    #[serde(default)]
+    pub active_kid: Option<String>,
+    /// generate a new signing key periodically, previous keys retire once their tokens expire
+    ///
+    /// Requires `key_dir`, rotated keys are written to it and retired keys removed from it.
+    #[serde(default)]
+    pub rotation_hours: Option<u64>,
+    #[serde(default)]
+    pub token: TokenConfig,
+    #[serde(default)]
+    pub validation: ValidationConfig,
+    #[serde(default)]
+    pub roles: RoleConfig,
+    #[serde(default)]
+    pub throttle: ThrottleConfig,
+    #[serde(default)]
+    pub reset: ResetConfig,
+    #[serde(default)]
+    pub mfa: MfaConfig,
+    /// clients of the OAuth2 client credentials grant
+    #[serde(default)]
+    pub clients: Vec<ClientConfig>,
+    /// cookie session mode for browser clients, disabled if absent
+    #[serde(default)]
+    pub session: Option<SessionConfig>,
+    /// external OpenID Connect providers whose tokens are accepted
+    #[serde(default)]
+    pub external_issuers: Vec<ExternalIssuerConfig>,
+}
+
+#[derive(Debug, Error)]
+pub enum AuthError {
+    #[error("jwt error: {0}")]
+    JWTError(#[from] jwt_simple::Error),
+    #[error("password hash error: {0}")]
+    PasswordHash(#[from] argon2::password_hash::Error),
+    #[error("store error: {0}")]
+    Store(#[from] StoreError),
+    #[error("refresh token is invalid or expired")]
+    InvalidRefreshToken,
+    #[error("refresh token has already been used")]
+    RefreshTokenReused,
+    #[error("token has no jti")]
+    MissingTokenId,
+    #[error("token has been revoked")]
+    TokenRevoked,
+    #[error("no signing key configured")]
+    NoSigningKey,
+    #[error("token has no kid")]
+    MissingKeyId,
+    #[error("unknown kid: {0}")]
+    UnknownKeyId(String),
+    #[error("invalid key: {0}")]
+    InvalidKey(String),
+    #[error("failed to read key {}: {1}", .0.display())]
+    KeyFile(std::path::PathBuf, std::io::Error),
+    #[error("failed to write key {}: {1}", .0.display())]
+    KeyWrite(std::path::PathBuf, std::io::Error),
+    #[error("key rotation requires key_dir, to share rotated keys across restarts and replicas")]
+    RotationWithoutKeyDir,
+    #[error("environment variable {0} of a key is not set")]
+    MissingKeyEnv(String),
+    #[error("token has expired")]
+    TokenExpired,
+    #[error("token issuer is not allowed: {0}")]
+    IssuerNotAllowed(String),
+    #[error("token audience does not contain {0}")]
+    AudienceMismatch(String),
+    #[error("token subject is not {0}")]
+    SubjectMismatch(String),
+    #[error("token was issued too long ago")]
+    TokenTooOld,
+    #[error("api key is invalid")]
+    InvalidApiKey,
+    #[error("api key has expired")]
+    ApiKeyExpired,
+    #[error("password reset token is invalid, expired or already used")]
+    InvalidResetToken,
+    #[error("mfa challenge token is invalid, expired or already used")]
+    InvalidChallengeToken,
+    #[error("totp error: {0}")]
+    Totp(String),
+    #[error("client id or secret is invalid")]
+    InvalidClient,
+    #[error("scope is not allowed: {0}")]
+    ScopeNotAllowed(String),
+    #[error("name of an external issuer is empty or reserved: {0}")]
+    ReservedIssuerName(String),
+    #[error("failed to fetch jwks: {0}")]
+    JwksFetch(String),
+    #[error("token has no claim {0}")]
+    MissingClaim(String),
+    #[error("session is revoked, expired or unknown")]
+    InvalidSession,
+}
+
+type Result<T> = std::result::Result<T, AuthError>;
+
+impl AuthError {
+    /// Whether the error is a failure of the server, e.g. of the store, rather than of the
+    /// credentials of the client.
+    pub fn is_internal(&self) -> bool {
+        matches!(
+            self,
+            Self::PasswordHash(_)
+                | Self::Store(_)
+                | Self::NoSigningKey
+                | Self::InvalidKey(_)
+                | Self::KeyFile(..)
+                | Self::KeyWrite(..)
+                | Self::RotationWithoutKeyDir
+                | Self::MissingKeyEnv(_)
+                | Self::Totp(_)
+                | Self::JwksFetch(_)
+                | Self::ReservedIssuerName(_)
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomClaims {
+    pub data: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub scopes: Vec<String>,
+    /// session of the signin, see [`Sessions`]
+    #[serde(default, rename = "sid", skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    /// claims configured in [`TokenConfig::extra_claims`]
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_json::Value>,
+}
+
+/// Signs access tokens. They are short lived, clients renew them with a refresh token.
+#[derive(Debug, Clone)]
+pub struct AuthSigner {
+    provider: String,
+    #[debug(skip)]
+    keyring: Arc<KeyRing>,
+    config: TokenConfig,
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthVerifier {
+    provider: String,
+    #[debug(skip)]
+    keyring: Arc<KeyRing>,
+    revocations: Arc<dyn RevocationStore>,
+    validation: ValidationConfig,
+    external: Vec<Arc<ExternalIssuer>>,
+}
+
+impl AuthSigner {
+    pub fn new(provider: impl Into<String>, keyring: Arc<KeyRing>, config: TokenConfig) -> Self {
+        Self {
+            provider: provider.into(),
+            keyring,
+            config,
+        }
+    }
+
+    /// Lifetime in seconds of a token, the requested one if it is shorter than the configured one.
+    pub fn lifetime(&self, requested: Option<u64>) -> u64 {
+        let max = self.config.lifetime_seconds;
+        requested.map_or(max, |secs| secs.min(max))
+    }
+
+    pub fn sign(&self, mut custom: CustomClaims, lifetime_seconds: u64) -> Result<String> {
+        let extra = self.config.extra_claims(&custom);
+        custom.extra.extend(extra);
+        let mut claims = Claims::with_custom_claims(custom, Duration::from_secs(lifetime_seconds))
+            .with_issuer(&self.provider)
+            .with_subject(validation::ACCESS_TOKEN_SUBJECT)
+            .with_jwt_id(uuid7::uuid7().to_string());
+        if !self.config.audiences.is_empty() {
+            let audiences: HashSet<_> = self.config.audiences.iter().collect();
+            claims = claims.with_audiences(audiences);
+        }
+        self.keyring.sign(claims)
+    }
+}
+
+impl CustomClaims {
+    pub fn new(data: impl Into<String>) -> Self {
+        Self {
+            data: data.into(),
+            roles: vec![],
+            scopes: vec![],
+            session_id: None,
+            extra: BTreeMap::new(),
+        }
+    }
+}
+
+impl AuthVerifier {
+    pub fn new(
+        provider: impl Into<String>,
+        keyring: Arc<KeyRing>,
+        revocations: Arc<dyn RevocationStore>,
+        validation: ValidationConfig,
+    ) -> Self {
+        Self {
+            provider: provider.into(),
+            keyring,
+            revocations,
+            validation,
+            external: vec![],
+        }
+    }
+
+    /// Also accept the tokens of external issuers.
+    pub fn with_external_issuers(mut self, issuers: Vec<ExternalIssuer>) -> Self {
+        self.external = issuers.into_iter().map(Arc::new).collect();
+        self
+    }
+
+    pub fn verify(&self, token: impl AsRef<str>) -> Result<JWTClaims<CustomClaims>> {
+        let token = token.as_ref();
+        let claims = self
+            .keyring
+            .verify::<CustomClaims>(token, self.validation.options())
+            .map_err(expired)?;
+        self.validation.validate(&claims, &self.provider)?;
+        let jti = claims.jwt_id.as_deref().ok_or(AuthError::MissingTokenId)?;
+        if self.revocations.is_revoked(jti)? {
+            return Err(AuthError::TokenRevoked);
+        }
+        Ok(claims)
+    }
+
+    /// Verify an access token of ours or of an external issuer, selected by its `iss`.
+    pub fn authenticate(&self, token: impl AsRef<str>) -> Result<Identity> {
+        let token = token.as_ref();
+        let Some(issuer) = self.external_issuer(token) else {
+            return self.verify(token).map(Identity::from);
+        };
+        let identity = issuer.verify(token)?;
+        // external tokens may lack a jti, they can't be revoked then
+        if let Some(jti) = &identity.jwt_id {
+            if self.revocations.is_revoked(jti)? {
+                return Err(AuthError::TokenRevoked);
+            }
+        }
+        Ok(identity)
+    }
+
+    /// The external issuer of a token whose keys must be fetched before it can be verified.
+    pub fn stale_issuer(&self, token: &str) -> Option<Arc<ExternalIssuer>> {
+        let issuer = self.external_issuer(token)?;
+        let metadata = Token::decode_metadata(token).ok()?;
+        let kid = metadata.key_id()?;
+        issuer.needs_refresh(kid).then(|| issuer.clone())
+    }
+
+    fn external_issuer(&self, token: &str) -> Option<&Arc<ExternalIssuer>> {
+        if self.external.is_empty() {
+            return None;
+        }
+        let iss = external::unverified_issuer(token)?;
+        self.external.iter().find(|e| e.issuer() == iss)
+    }
+
+    pub fn provider(&self) -> &str {
+        &self.provider
+    }
+
+    /// Public keys that verify tokens signed by us.
+    pub fn jwks(&self) -> Jwks {
+        self.keyring.jwks()
+    }
+
+    /// Revoke the token of a verified identity until it expires.
+    pub fn revoke(&self, identity: &Identity) -> Result<()> {
+        let jti = identity
+            .jwt_id
+            .as_deref()
+            .ok_or(AuthError::MissingTokenId)?;
+        let expires_at = identity.expires_at.unwrap_or_else(store::now);
+        self.revocations.revoke(jti, expires_at)?;
+        Ok(())
+    }
+}
+
+impl AuthConfig {
+    /// Keys of the config and of the key directory. With rotation, rotated keys are kept in the
+    /// key directory, the first replica to start creates one if it has none.
+    pub fn keyring(&self) -> Result<KeyRing> {
+        let rotation_dir = match (self.rotation_hours, &self.key_dir) {
+            (Some(_), None) => return Err(AuthError::RotationWithoutKeyDir),
+            (Some(_), Some(dir)) => Some(dir),
+            (None, _) => None,
+        };
+        let mut keys = self
+            .keys
+            .iter()
+            .map(KeyConfig::load)
+            .collect::<Result<Vec<_>>>()?;
+        if let Some(dir) = &self.key_dir {
+            keys.extend(KeyConfig::load_dir(dir, self.algorithm)?);
+        }
+        if let (Some(dir), true) = (rotation_dir, keys.iter().all(|k| k.sk.is_none())) {
+            let (kid, sk) = keyring::generate_key(self.algorithm, Some(dir))?;
+            keys.push(KeyConfig {
+                kid: Some(kid),
+                alg: Some(self.algorithm),
+                sk: Some(sk.to_pem()?),
+                ..Default::default()
+            });
+        }
+        let keyring = KeyRing::try_new(
+            &keys,
+            self.active_kid.as_deref(),
+            self.algorithm,
+            self.retire_after(),
+        )?;
+        Ok(match rotation_dir {
+            Some(dir) => keyring.with_dir(dir.clone()),
+            None => keyring,
+        })
+    }
+
+    /// Seconds a rotated key keeps verifying, it must outlive every token it signed: access
+    /// and client tokens, password reset and MFA challenge tokens.
+    fn retire_after(&self) -> u64 {
+        let lifetime = self
+            .token
+            .lifetime_seconds
+            .max(self.reset.lifetime_seconds)
+            .max(self.mfa.challenge_lifetime_seconds);
+        lifetime + self.validation.clock_skew_seconds
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        let algorithm = Algorithm::default();
+        Self {
+            algorithm,
+            keys: vec![KeyConfig::generate(algorithm).expect("failed to generate key")],
+            key_dir: None,
+            active_kid: None,
+            rotation_hours: None,
+            token: TokenConfig::default(),
+            validation: ValidationConfig::default(),
+            roles: RoleConfig::default(),
+            throttle: ThrottleConfig::default(),
+            reset: ResetConfig::default(),
+            mfa: MfaConfig::default(),
+            clients: vec![],
+            session: None,
+            external_issuers: vec![],
+        }
+    }
+}
+
+/// Map the expiry error of jwt-simple to [`AuthError::TokenExpired`].
+fn expired(e: AuthError) -> AuthError {
+    match e {
+        AuthError::JWTError(e)
+            if matches!(
+                e.downcast_ref::<JWTError>(),
+                Some(JWTError::TokenHasExpired)
+            ) =>
+        {
+            AuthError::TokenExpired
+        }
+        e => e,
+    }
+}
+
+/// A server error that keeps the internals of `e` out of the response, they are logged instead.
+fn server_error(e: AuthError) -> ServerError {
+    error!("auth error: {}", e);
+    let code = match e {
+        AuthError::Store(_) => ErrorCode::Database,
+        _ => ErrorCode::Unknown,
+    };
+    ServerError {
+        code,
+        message: "internal server error".to_string(),
+    }
+}
+
+impl From<AuthError> for SigninError {
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::InvalidChallengeToken | AuthError::InvalidSession => {
+                Self::UnauthorizedError(UnauthorizedError {
+                    message: e.to_string(),
+                })
+            }
+            _ => Self::ServerError(server_error(e)),
+        }
+    }
+}
+
+impl From<AuthError> for RefreshTokenError {
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::InvalidRefreshToken
+            | AuthError::RefreshTokenReused
+            | AuthError::InvalidSession => Self::UnauthorizedError(UnauthorizedError {
+                message: e.to_string(),
+            }),
+            _ => Self::ServerError(server_error(e)),
+        }
+    }
+}
+
+impl From<AuthError> for SignoutError {
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::InvalidRefreshToken => Self::UnauthorizedError(UnauthorizedError {
+                message: e.to_string(),
+            }),
+            _ => Self::ServerError(server_error(e)),
+        }
+    }
+}
+
+impl From<AuthError> for CreateApiKeyError {
+    fn from(e: AuthError) -> Self {
+        Self::ServerError(server_error(e))
+    }
+}
+
+impl From<AuthError> for ListApiKeysError {
+    fn from(e: AuthError) -> Self {
+        Self::ServerError(server_error(e))
+    }
+}
+
+impl From<AuthError> for RevokeApiKeyError {
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::InvalidApiKey => Self::NotFoundError(NotFoundError {
+                message: "api key not found".to_string(),
+            }),
+            _ => Self::ServerError(server_error(e)),
+        }
+    }
+}
+
+impl From<AuthError> for ListSessionsError {
+    fn from(e: AuthError) -> Self {
+        Self::ServerError(server_error(e))
+    }
+}
+
+impl From<AuthError> for RevokeSessionError {
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::InvalidSession => Self::NotFoundError(NotFoundError {
+                message: "session not found".to_string(),
+            }),
+            _ => Self::ServerError(server_error(e)),
+        }
+    }
+}
+
+impl From<AuthError> for EnrollMfaError {
+    fn from(e: AuthError) -> Self {
+        Self::ServerError(server_error(e))
+    }
+}
+
+impl From<AuthError> for ConfirmMfaError {
+    fn from(e: AuthError) -> Self {
+        Self::ServerError(server_error(e))
+    }
+}
+
+impl From<AuthError> for ConfirmPasswordResetError {
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::InvalidResetToken => Self::UnauthorizedError(UnauthorizedError {
+                message: e.to_string(),
+            }),
+            _ => Self::ServerError(server_error(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryRevocationStore;
+
+    fn signer_verifier() -> (AuthSigner, AuthVerifier) {
+        let keyring = Arc::new(AuthConfig::default().keyring().unwrap());
+        let signer = AuthSigner::new("test", keyring.clone(), TokenConfig::default());
+        let revocations = Arc::new(MemoryRevocationStore::default());
+        let verifier = AuthVerifier::new("test", keyring, revocations, Default::default());
+        (signer, verifier)
+    }
+
+    #[test]
+    fn signed_token_should_verify() {
+        let (signer, verifier) = signer_verifier();
+        let mut custom = CustomClaims::new("alice");
+        custom.scopes = vec!["echo:write".to_string()];
+        let token = signer.sign(custom, 60).unwrap();
+        let claims = verifier.verify(&token).unwrap();
+        assert_eq!(claims.custom.data, "alice");
+        assert_eq!(claims.custom.scopes, vec!["echo:write"]);
+        assert!(claims.jwt_id.is_some());
+    }
+
+    #[test]
+    fn token_should_carry_configured_claims() {
+        let (mut signer, mut verifier) = signer_verifier();
+        signer.config.audiences = vec!["gateway".to_string()];
+        signer
+            .config
+            .extra_claims
+            .insert("tenant".to_string(), ClaimValue::Static("acme".into()));
+        verifier.validation.required_audience = Some("gateway".to_string());
+        assert_eq!(
+            signer.lifetime(Some(u64::MAX)),
+            signer.config.lifetime_seconds
+        );
+
+        let token = signer
+            .sign(CustomClaims::new("alice"), signer.lifetime(Some(60)))
+            .unwrap();
+        let claims = verifier.verify(&token).unwrap();
+        assert_eq!(claims.custom.extra["tenant"], "acme");
+        let lifetime = claims.expires_at.unwrap().as_secs() - claims.issued_at.unwrap().as_secs();
+        assert_eq!(lifetime, 60);
+    }
+
+    #[test]
+    fn token_of_other_issuer_should_not_verify() {
+        let keyring = Arc::new(AuthConfig::default().keyring().unwrap());
+        let signer = AuthSigner::new("other", keyring.clone(), TokenConfig::default());
+        let revocations = Arc::new(MemoryRevocationStore::default());
+        let verifier = AuthVerifier::new("test", keyring, revocations, Default::default());
+        let token = signer.sign(CustomClaims::new("alice"), 60).unwrap();
+        assert!(matches!(
+            verifier.verify(&token),
+            Err(AuthError::IssuerNotAllowed(_))
+        ));
+    }
+
+    #[test]
+    fn token_without_required_audience_should_not_verify() {
+        let (signer, mut verifier) = signer_verifier();
+        verifier.validation.required_audience = Some("gateway".to_string());
+        let token = signer.sign(CustomClaims::new("alice"), 60).unwrap();
+        assert!(matches!(
+            verifier.verify(&token),
+            Err(AuthError::AudienceMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn token_of_other_subject_should_not_verify() {
+        let (signer, verifier) = signer_verifier();
+        let claims =
+            Claims::with_custom_claims(CustomClaims::new("alice"), Duration::from_secs(60))
+                .with_issuer("test")
+                .with_subject("password-reset")
+                .with_jwt_id(uuid7::uuid7().to_string());
+        let token = signer.keyring.sign(claims).unwrap();
+        assert!(matches!(
+            verifier.verify(&token),
+            Err(AuthError::SubjectMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn retired_key_should_outlive_reset_tokens() {
+        let mut config = AuthConfig::default();
+        config.token.lifetime_seconds = 300;
+        config.reset.lifetime_seconds = 1800;
+        config.validation.clock_skew_seconds = 60;
+        assert_eq!(config.retire_after(), 1860);
+    }
+
+    #[test]
+    fn revoked_token_should_not_verify() {
+        let (signer, verifier) = signer_verifier();
+        let token = signer.sign(CustomClaims::new("alice"), 60).unwrap();
+        let claims = verifier.verify(&token).unwrap();
+        verifier.revoke(&claims.into()).unwrap();
+        assert!(matches!(
+            verifier.verify(&token),
+            Err(AuthError::TokenRevoked)
+        ));
+    }
+}
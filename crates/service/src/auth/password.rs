@@ -0,0 +1,68 @@
+use super::Result;
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use std::sync::OnceLock;
+
+/// Hash a password with argon2id and a random salt, returns the PHC string.
+pub async fn hash_password(password: &str) -> Result<String> {
+    let password = password.to_string();
+    blocking(move || hash(&password)).await
+}
+
+/// Verify a password against a PHC string produced by [`hash_password`].
+pub async fn verify_password(password: &str, hash: &str) -> bool {
+    let (password, hash) = (password.to_string(), hash.to_string());
+    blocking(move || verify(&password, &hash)).await
+}
+
+/// Burn the same amount of time as [`verify_password`] when there is no hash to verify against,
+/// so that unknown usernames can't be told apart by response time.
+pub async fn dummy_verify_password(password: &str) {
+    static DUMMY: OnceLock<String> = OnceLock::new();
+    let password = password.to_string();
+    blocking(move || {
+        let hash = DUMMY.get_or_init(|| hash("dummy password").unwrap_or_default());
+        verify(&password, hash);
+    })
+    .await
+}
+
+/// Run argon2 on the blocking pool, it takes too long for the async workers that serve requests.
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    match tokio::task::spawn_blocking(f).await {
+        Ok(value) => value,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+fn hash(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default().hash_password(password.as_bytes(), &salt)?;
+    Ok(hash.to_string())
+}
+
+fn verify(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .and_then(|hash| Argon2::default().verify_password(password.as_bytes(), &hash))
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn hashed_password_should_verify() {
+        let hash = hash_password("abcd1234").await.unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password("abcd1234", &hash).await);
+        assert!(!verify_password("abcd12345", &hash).await);
+    }
+
+    #[tokio::test]
+    async fn invalid_hash_should_not_verify() {
+        assert!(!verify_password("abcd1234", "not a hash").await);
+    }
+}
@@ -0,0 +1,153 @@
+use super::{AuthError, AuthSigner, AuthVerifier, Result};
+use crate::store;
+use jwt_simple::prelude::*;
+
+const DEFAULT_RESET_LIFETIME_SECONDS: u64 = 30 * 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResetConfig {
+    /// lifetime of password reset tokens
+    #[serde(default = "default_reset_lifetime")]
+    pub lifetime_seconds: u64,
+}
+
+/// What a single use token allows, besides accessing the api like an access token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Purpose {
+    /// reset the password of the user
+    PasswordReset,
+    /// finish a signin with an MFA code
+    MfaChallenge,
+}
+
+/// Claims of a purpose token, they don't deserialize as the claims of an access token.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PurposeClaims {
+    /// username the token is issued to
+    user: String,
+}
+
+/// A verified purpose token, it remains valid until consumed or expired.
+#[derive(Debug, Clone)]
+pub struct PurposeToken {
+    pub username: String,
+    jwt_id: String,
+    expires_at: u64,
+}
+
+impl Purpose {
+    /// `sub` of the tokens, access tokens have `auth`.
+    fn subject(self) -> &'static str {
+        match self {
+            Self::PasswordReset => "password-reset",
+            Self::MfaChallenge => "mfa-challenge",
+        }
+    }
+
+    fn invalid(self) -> AuthError {
+        match self {
+            Self::PasswordReset => AuthError::InvalidResetToken,
+            Self::MfaChallenge => AuthError::InvalidChallengeToken,
+        }
+    }
+}
+
+impl AuthSigner {
+    /// Sign a single use token for a step of a flow of a user.
+    pub fn sign_for(
+        &self,
+        purpose: Purpose,
+        username: &str,
+        lifetime_seconds: u64,
+    ) -> Result<String> {
+        let custom = PurposeClaims {
+            user: username.to_string(),
+        };
+        let claims = Claims::with_custom_claims(custom, Duration::from_secs(lifetime_seconds))
+            .with_issuer(&self.provider)
+            .with_subject(purpose.subject())
+            .with_jwt_id(uuid7::uuid7().to_string());
+        self.keyring.sign(claims)
+    }
+}
+
+impl AuthVerifier {
+    /// Verify a token signed for `purpose` that has not been consumed yet.
+    pub fn verify_for(&self, purpose: Purpose, token: &str) -> Result<PurposeToken> {
+        let claims = self
+            .keyring
+            .verify::<PurposeClaims>(token, self.validation.options())
+            .map_err(|_| purpose.invalid())?;
+        if claims.issuer.as_deref() != Some(self.provider.as_str())
+            || claims.subject.as_deref() != Some(purpose.subject())
+        {
+            return Err(purpose.invalid());
+        }
+        let jwt_id = claims.jwt_id.ok_or(AuthError::MissingTokenId)?;
+        if self.revocations.is_revoked(&jwt_id)? {
+            return Err(purpose.invalid());
+        }
+        Ok(PurposeToken {
+            username: claims.custom.user,
+            jwt_id,
+            expires_at: claims
+                .expires_at
+                .map(|t| t.as_secs())
+                .unwrap_or_else(store::now),
+        })
+    }
+
+    /// Revoke a purpose token once its step is done.
+    pub fn consume(&self, token: &PurposeToken) -> Result<()> {
+        self.revocations.revoke(&token.jwt_id, token.expires_at)?;
+        Ok(())
+    }
+}
+
+impl Default for ResetConfig {
+    fn default() -> Self {
+        Self {
+            lifetime_seconds: DEFAULT_RESET_LIFETIME_SECONDS,
+        }
+    }
+}
+
+fn default_reset_lifetime() -> u64 {
+    DEFAULT_RESET_LIFETIME_SECONDS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::{AuthConfig, CustomClaims, TokenConfig},
+        store::MemoryRevocationStore,
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn purpose_token_should_be_single_use() {
+        let keyring = Arc::new(AuthConfig::default().keyring().unwrap());
+        let signer = AuthSigner::new("test", keyring.clone(), TokenConfig::default());
+        let revocations = Arc::new(MemoryRevocationStore::default());
+        let verifier = AuthVerifier::new("test", keyring, revocations, Default::default());
+
+        let token = signer
+            .sign_for(Purpose::PasswordReset, "alice", 60)
+            .unwrap();
+        assert!(verifier.verify(&token).is_err());
+        assert!(verifier.verify_for(Purpose::MfaChallenge, &token).is_err());
+        let verified = verifier.verify_for(Purpose::PasswordReset, &token).unwrap();
+        assert_eq!(verified.username, "alice");
+        verifier.consume(&verified).unwrap();
+        assert!(matches!(
+            verifier.verify_for(Purpose::PasswordReset, &token),
+            Err(AuthError::InvalidResetToken)
+        ));
+
+        let access_token = signer.sign(CustomClaims::new("alice"), 60).unwrap();
+        assert!(verifier
+            .verify_for(Purpose::PasswordReset, &access_token)
+            .is_err());
+    }
+}
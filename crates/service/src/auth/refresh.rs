@@ -0,0 +1,144 @@
+use super::{AuthError, Result};
+use crate::store::{self, RefreshToken, RefreshTokenStore};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+pub const REFRESH_TOKEN_DAYS: u64 = 14;
+
+/// Issues opaque refresh tokens and rotates them on every use.
+///
+/// Each signin starts a new token family. A used token being presented again means it was
+/// stolen, so the whole family is revoked and both the thief and the victim have to signin again.
+#[derive(Debug, Clone)]
+pub struct RefreshTokens {
+    store: Arc<dyn RefreshTokenStore>,
+}
+
+impl RefreshTokens {
+    pub fn new(store: Arc<dyn RefreshTokenStore>) -> Self {
+        Self { store }
+    }
+
+    /// Issue the first refresh token of a family, the family is the session of the signin.
+    pub fn issue(&self, username: &str, family: &str) -> Result<String> {
+        self.issue_in_family(username, family.to_string())
+    }
+
+    /// Exchange a refresh token for a new one in the same family, returns the used token and the new token.
+    pub fn rotate(&self, token: &str) -> Result<(RefreshToken, String)> {
+        let Some(old) = self.store.mark_used(&hash_token(token))? else {
+            return Err(AuthError::InvalidRefreshToken);
+        };
+        if old.used {
+            self.store.revoke_family(&old.family)?;
+            return Err(AuthError::RefreshTokenReused);
+        }
+        if old.expires_at <= store::now() {
+            return Err(AuthError::InvalidRefreshToken);
+        }
+
+        let token = self.issue_in_family(&old.username, old.family.clone())?;
+        Ok((old, token))
+    }
+
+    /// The stored refresh token, used or not, fails if it is unknown.
+    pub fn get(&self, token: &str) -> Result<RefreshToken> {
+        self.store
+            .get(&hash_token(token))?
+            .ok_or(AuthError::InvalidRefreshToken)
+    }
+
+    /// Revoke the family of a refresh token, only if it belongs to `username`.
+    pub fn revoke(&self, token: &str, username: &str) -> Result<()> {
+        match self.store.get(&hash_token(token))? {
+            Some(t) if t.username == username => Ok(self.store.revoke_family(&t.family)?),
+            _ => Err(AuthError::InvalidRefreshToken),
+        }
+    }
+
+    /// Revoke all tokens of a family, e.g. of a revoked session.
+    pub fn revoke_family(&self, family: &str) -> Result<()> {
+        Ok(self.store.revoke_family(family)?)
+    }
+
+    fn issue_in_family(&self, username: &str, family: String) -> Result<String> {
+        let token = generate_token();
+        self.store.insert(RefreshToken {
+            hash: hash_token(&token),
+            family,
+            username: username.to_string(),
+            expires_at: store::now() + REFRESH_TOKEN_DAYS * 24 * 3600,
+            used: false,
+        })?;
+        Ok(token)
+    }
+}
+
+/// Generate a random url safe token with 256 bits of entropy.
+pub fn generate_token() -> String {
+    let mut buf = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut buf);
+    URL_SAFE_NO_PAD.encode(buf)
+}
+
+/// Hex encoded sha256 of a token. Tokens are random enough that a slow hash is not needed.
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Compare two hashes in constant time, so a match can't be found byte by byte.
+pub fn hashes_equal(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryRefreshTokenStore;
+
+    fn refresh_tokens() -> RefreshTokens {
+        RefreshTokens::new(Arc::new(MemoryRefreshTokenStore::default()))
+    }
+
+    #[test]
+    fn rotate_should_issue_new_token() {
+        let tokens = refresh_tokens();
+        let token = tokens.issue("alice", "session").unwrap();
+        let (old, new_token) = tokens.rotate(&token).unwrap();
+        assert_eq!(old.username, "alice");
+        assert_eq!(old.family, "session");
+        assert_ne!(token, new_token);
+        assert!(tokens.rotate(&new_token).is_ok());
+    }
+
+    #[test]
+    fn reused_token_should_revoke_family() {
+        let tokens = refresh_tokens();
+        let token = tokens.issue("alice", "session").unwrap();
+        let (_, new_token) = tokens.rotate(&token).unwrap();
+
+        assert!(matches!(
+            tokens.rotate(&token),
+            Err(AuthError::RefreshTokenReused)
+        ));
+        assert!(matches!(
+            tokens.rotate(&new_token),
+            Err(AuthError::InvalidRefreshToken)
+        ));
+    }
+
+    #[test]
+    fn unknown_token_should_be_rejected() {
+        let tokens = refresh_tokens();
+        assert!(matches!(
+            tokens.rotate("unknown"),
+            Err(AuthError::InvalidRefreshToken)
+        ));
+    }
+}
@@ -0,0 +1,56 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+
+/// Roles of users and the scopes they grant to their tokens.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoleConfig {
+    /// scopes granted by each role
+    #[serde(default)]
+    pub scopes: HashMap<String, Vec<String>>,
+    /// roles given to new users at signup
+    #[serde(default)]
+    pub default_roles: Vec<String>,
+}
+
+impl RoleConfig {
+    /// Sorted union of the scopes granted by `roles`, unknown roles grant nothing.
+    pub fn scopes(&self, roles: &[String]) -> Vec<String> {
+        let scopes: BTreeSet<_> = roles
+            .iter()
+            .filter_map(|r| self.scopes.get(r))
+            .flatten()
+            .cloned()
+            .collect();
+        scopes.into_iter().collect()
+    }
+}
+
+impl Default for RoleConfig {
+    fn default() -> Self {
+        Self {
+            scopes: HashMap::from([("user".to_string(), vec!["echo:write".to_string()])]),
+            default_roles: vec!["user".to_string()],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scopes_should_merge_roles() {
+        let config = RoleConfig {
+            scopes: HashMap::from([
+                ("user".to_string(), vec!["echo:write".to_string()]),
+                (
+                    "admin".to_string(),
+                    vec!["echo:write".to_string(), "users:admin".to_string()],
+                ),
+            ]),
+            default_roles: vec![],
+        };
+        let roles = ["admin".to_string(), "user".to_string(), "guest".to_string()];
+        assert_eq!(config.scopes(&roles), vec!["echo:write", "users:admin"]);
+    }
+}
@@ -0,0 +1,167 @@
+use super::refresh::generate_token;
+use axum::http::{header, HeaderMap, Method};
+use serde::{Deserialize, Serialize};
+
+/// Session mode for browser clients: the access token is kept in an HttpOnly cookie.
+///
+/// Cookies are sent by the browser on cross site requests too, so state changing requests
+/// authenticated by the cookie must echo the CSRF cookie in the CSRF header (double submit).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionConfig {
+    /// HttpOnly cookie carrying the access token
+    #[serde(default = "default_cookie")]
+    pub cookie: String,
+    /// cookie readable by scripts carrying the CSRF token
+    #[serde(default = "default_csrf_cookie")]
+    pub csrf_cookie: String,
+    /// header the CSRF token must be echoed in
+    #[serde(default = "default_csrf_header")]
+    pub csrf_header: String,
+    #[serde(default = "default_path")]
+    pub path: String,
+    #[serde(default)]
+    pub same_site: SameSite,
+    /// only send the cookies over https, disable for local development over http
+    #[serde(default = "default_secure")]
+    pub secure: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SameSite {
+    #[default]
+    Strict,
+    Lax,
+}
+
+impl SessionConfig {
+    /// `Set-Cookie` values starting a session with an access token valid for `max_age` seconds.
+    pub fn issue(&self, token: &str, max_age: u64) -> [String; 2] {
+        [
+            self.cookie(&self.cookie, token, max_age, true),
+            self.cookie(&self.csrf_cookie, &generate_token(), max_age, false),
+        ]
+    }
+
+    /// `Set-Cookie` values ending the session.
+    pub fn clear(&self) -> [String; 2] {
+        [
+            self.cookie(&self.cookie, "", 0, true),
+            self.cookie(&self.csrf_cookie, "", 0, false),
+        ]
+    }
+
+    /// Access token of the session cookie of a request.
+    pub fn token<'a>(&self, headers: &'a HeaderMap) -> Option<&'a str> {
+        cookie(headers, &self.cookie).filter(|v| !v.is_empty())
+    }
+
+    /// Whether the request may be authenticated by the session cookie.
+    ///
+    /// Safe methods don't need the CSRF token, they must not change state anyway.
+    pub fn check_csrf(&self, method: &Method, headers: &HeaderMap) -> bool {
+        if matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) {
+            return true;
+        }
+        let submitted = headers
+            .get(self.csrf_header.as_str())
+            .and_then(|v| v.to_str().ok());
+        match (cookie(headers, &self.csrf_cookie), submitted) {
+            (Some(expected), Some(submitted)) => !expected.is_empty() && expected == submitted,
+            _ => false,
+        }
+    }
+
+    fn cookie(&self, name: &str, value: &str, max_age: u64, http_only: bool) -> String {
+        let mut cookie = format!(
+            "{}={}; Path={}; Max-Age={}; SameSite={:?}",
+            name, value, self.path, max_age, self.same_site
+        );
+        if http_only {
+            cookie.push_str("; HttpOnly");
+        }
+        if self.secure {
+            cookie.push_str("; Secure");
+        }
+        cookie
+    }
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            cookie: default_cookie(),
+            csrf_cookie: default_csrf_cookie(),
+            csrf_header: default_csrf_header(),
+            path: default_path(),
+            same_site: SameSite::default(),
+            secure: default_secure(),
+        }
+    }
+}
+
+/// Value of a cookie in the `Cookie` headers of a request.
+fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| v)
+}
+
+fn default_cookie() -> String {
+    "session".to_string()
+}
+
+fn default_csrf_cookie() -> String {
+    "csrf_token".to_string()
+}
+
+fn default_csrf_header() -> String {
+    "x-csrf-token".to_string()
+}
+
+fn default_path() -> String {
+    "/".to_string()
+}
+
+fn default_secure() -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn state_changing_request_should_echo_csrf_cookie() {
+        let config = SessionConfig::default();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::COOKIE,
+            HeaderValue::from_static("session=jwt; csrf_token=abc"),
+        );
+        assert_eq!(config.token(&headers), Some("jwt"));
+        assert!(config.check_csrf(&Method::GET, &headers));
+        assert!(!config.check_csrf(&Method::POST, &headers));
+
+        headers.insert("x-csrf-token", HeaderValue::from_static("xyz"));
+        assert!(!config.check_csrf(&Method::POST, &headers));
+        headers.insert("x-csrf-token", HeaderValue::from_static("abc"));
+        assert!(config.check_csrf(&Method::POST, &headers));
+    }
+
+    #[test]
+    fn cookies_should_carry_attributes() {
+        let [session, csrf] = SessionConfig::default().issue("jwt", 60);
+        assert_eq!(
+            session,
+            "session=jwt; Path=/; Max-Age=60; SameSite=Strict; HttpOnly; Secure"
+        );
+        assert!(csrf.starts_with("csrf_token="));
+        assert!(!csrf.contains("HttpOnly"));
+    }
+}
@@ -0,0 +1,174 @@
+use super::{refresh::REFRESH_TOKEN_DAYS, AuthError, Identity, Result};
+use crate::store::{self, Session, SessionStore, StoreError};
+use std::{net::IpAddr, sync::Arc};
+
+/// Minimum interval between updates of the last seen time of a session.
+const TOUCH_INTERVAL_SECONDS: u64 = 60;
+
+/// Signins of users on their devices, so users can see them and sign out the other devices.
+///
+/// A session shares its id with the refresh token family of the signin, and the access tokens
+/// of the signin carry it as `sid`. Removing the session rejects them right away.
+#[derive(Debug, Clone)]
+pub struct Sessions {
+    store: Arc<dyn SessionStore>,
+}
+
+impl Sessions {
+    pub fn new(store: Arc<dyn SessionStore>) -> Self {
+        Self { store }
+    }
+
+    /// Start the session of a signin.
+    pub fn start(
+        &self,
+        username: &str,
+        user_agent: Option<&str>,
+        ip: Option<IpAddr>,
+    ) -> Result<Session> {
+        let now = store::now();
+        let session = Session {
+            id: uuid7::uuid7().to_string(),
+            username: username.to_string(),
+            user_agent: user_agent.map(ToString::to_string),
+            ip: ip.map(|ip| ip.to_string()),
+            created_at: now,
+            last_seen_at: now,
+            expires_at: now + REFRESH_TOKEN_DAYS * 24 * 3600,
+        };
+        self.store.insert(session.clone())?;
+        Ok(session)
+    }
+
+    /// Reject an identity whose session was revoked, and record the session as seen.
+    ///
+    /// Identities without a session, like those of API keys, are accepted as is.
+    pub fn check(&self, identity: Identity) -> Result<Identity> {
+        let Some(id) = &identity.session_id else {
+            return Ok(identity);
+        };
+        let session = self.store.get(id)?.ok_or(AuthError::InvalidSession)?;
+        let now = store::now();
+        // a write per request would be wasted, the time is only shown to the user
+        if now >= session.last_seen_at + TOUCH_INTERVAL_SECONDS {
+            self.store.touch(id, now)?;
+        }
+        Ok(identity)
+    }
+
+    /// Fail with [`AuthError::InvalidSession`] if the session was ended or has expired.
+    pub fn ensure_active(&self, id: &str) -> Result<()> {
+        self.store.get(id)?.ok_or(AuthError::InvalidSession)?;
+        Ok(())
+    }
+
+    /// Extend a session whose refresh token was rotated, like the new refresh token.
+    pub fn refreshed(&self, id: &str) -> Result<()> {
+        let session = self.store.get(id)?.ok_or(AuthError::InvalidSession)?;
+        let now = store::now();
+        self.store.update(Session {
+            last_seen_at: now,
+            expires_at: now + REFRESH_TOKEN_DAYS * 24 * 3600,
+            ..session
+        })?;
+        Ok(())
+    }
+
+    pub fn list(&self, username: &str) -> Result<Vec<Session>> {
+        Ok(self.store.list(username)?)
+    }
+
+    /// End the sessions of a user except `keep`, e.g. after a password change, returns their ids
+    /// to revoke their refresh token families.
+    pub fn revoke_all(&self, username: &str, keep: Option<&str>) -> Result<Vec<String>> {
+        let mut revoked = vec![];
+        for session in self.store.list(username)? {
+            if Some(session.id.as_str()) == keep {
+                continue;
+            }
+            match self.store.remove(&session.id) {
+                // removed concurrently, e.g. by the user signing out
+                Ok(()) | Err(StoreError::NotFound(_)) => revoked.push(session.id),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(revoked)
+    }
+
+    /// End a session, only if it belongs to `username`.
+    pub fn revoke(&self, id: &str, username: &str) -> Result<()> {
+        match self.store.get(id)? {
+            Some(s) if s.username == username => Ok(self.store.remove(id)?),
+            _ => Err(AuthError::InvalidSession),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::RefreshTokens,
+        store::{MemoryRefreshTokenStore, MemorySessionStore},
+    };
+
+    #[test]
+    fn revoked_session_should_reject_its_identity() {
+        let sessions = Sessions::new(Arc::new(MemorySessionStore::default()));
+        let session = sessions
+            .start("alice", Some("curl/8.4.0"), "10.0.0.1".parse().ok())
+            .unwrap();
+        sessions.start("alice", None, None).unwrap();
+        assert_eq!(sessions.list("alice").unwrap().len(), 2);
+
+        let identity = Identity {
+            username: "alice".to_string(),
+            session_id: Some(session.id.clone()),
+            ..Default::default()
+        };
+        assert!(sessions.check(identity.clone()).is_ok());
+        assert!(sessions.revoke(&session.id, "bob").is_err());
+        sessions.revoke(&session.id, "alice").unwrap();
+        assert!(matches!(
+            sessions.check(identity),
+            Err(AuthError::InvalidSession)
+        ));
+        assert_eq!(sessions.list("alice").unwrap().len(), 1);
+        assert!(sessions.check(Identity::default()).is_ok());
+    }
+
+    #[test]
+    fn revoke_all_should_keep_the_current_session() {
+        let sessions = Sessions::new(Arc::new(MemorySessionStore::default()));
+        let refresh_tokens = RefreshTokens::new(Arc::new(MemoryRefreshTokenStore::default()));
+        let signin = |username: &str| {
+            let session = sessions.start(username, None, None).unwrap();
+            let token = refresh_tokens.issue(username, &session.id).unwrap();
+            (session.id, token)
+        };
+        let (current, current_token) = signin("alice");
+        let (other, other_token) = signin("alice");
+        let (_, bob_token) = signin("bob");
+
+        let revoked = sessions.revoke_all("alice", Some(&current)).unwrap();
+        assert_eq!(revoked, vec![other.clone()]);
+        for family in &revoked {
+            refresh_tokens.revoke_family(family).unwrap();
+        }
+        let identity = |session_id: &str| Identity {
+            username: "alice".to_string(),
+            session_id: Some(session_id.to_string()),
+            ..Default::default()
+        };
+        assert!(sessions.check(identity(&current)).is_ok());
+        assert!(sessions.check(identity(&other)).is_err());
+        assert!(refresh_tokens.rotate(&current_token).is_ok());
+        assert!(refresh_tokens.rotate(&other_token).is_err());
+        assert!(refresh_tokens.rotate(&bob_token).is_ok());
+
+        // a reset signs out every device
+        sessions.revoke_all("alice", None).unwrap();
+        assert!(sessions.list("alice").unwrap().is_empty());
+        assert_eq!(sessions.list("bob").unwrap().len(), 1);
+    }
+}
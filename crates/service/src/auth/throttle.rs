@@ -0,0 +1,214 @@
+use crate::store;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, net::IpAddr, sync::Mutex};
+use tracing::warn;
+
+/// Lockout of signin after repeated failures, per username and per client ip.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThrottleConfig {
+    /// failures allowed before the first lockout
+    #[serde(default = "default_free_attempts")]
+    pub free_attempts: u32,
+    /// first lockout, doubled on every further failure
+    #[serde(default = "default_base_lockout")]
+    pub base_lockout_seconds: u64,
+    #[serde(default = "default_max_lockout")]
+    pub max_lockout_seconds: u64,
+    /// tracked usernames and ips, the least recently failed are dropped beyond this
+    #[serde(default = "default_max_entries")]
+    pub max_entries: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Attempts {
+    failures: u32,
+    /// unix timestamps in seconds
+    last_failure: u64,
+    locked_until: u64,
+}
+
+/// Tracks failed signin attempts in memory with exponential lockout.
+#[derive(Debug)]
+pub struct AttemptTracker {
+    config: ThrottleConfig,
+    attempts: Mutex<HashMap<String, Attempts>>,
+}
+
+impl AttemptTracker {
+    pub fn new(config: ThrottleConfig) -> Self {
+        Self {
+            config,
+            attempts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Seconds before the username or the ip may try again, `None` if neither is locked out.
+    pub fn locked(&self, username: &str, ip: Option<IpAddr>) -> Option<u64> {
+        self.locked_at(&keys(username, ip), store::now())
+    }
+
+    /// Record a failed attempt, returns the lockout in seconds if it starts one.
+    pub fn failed(&self, username: &str, ip: Option<IpAddr>) -> Option<u64> {
+        self.failed_at(&keys(username, ip), store::now())
+    }
+
+    /// Forget the failures of a username after a successful signin.
+    ///
+    /// Failures of the ip are kept, otherwise an attacker could reset them with an account of their own.
+    pub fn succeeded(&self, username: &str) {
+        self.attempts.lock().unwrap().remove(&user_key(username));
+    }
+
+    fn locked_at(&self, keys: &[String], now: u64) -> Option<u64> {
+        let attempts = self.attempts.lock().unwrap();
+        keys.iter()
+            .filter_map(|k| attempts.get(k))
+            .map(|a| a.locked_until.saturating_sub(now))
+            .filter(|secs| *secs > 0)
+            .max()
+    }
+
+    fn failed_at(&self, keys: &[String], now: u64) -> Option<u64> {
+        let mut attempts = self.attempts.lock().unwrap();
+        let mut lockout = None;
+        for key in keys {
+            if !attempts.contains_key(key) {
+                self.make_room(&mut attempts, now);
+            }
+            let entry = attempts.entry(key.clone()).or_default();
+            // failures are forgotten once the longest lockout has passed since the last one
+            if now.saturating_sub(entry.last_failure) > self.config.max_lockout_seconds {
+                *entry = Attempts::default();
+            }
+            entry.failures += 1;
+            entry.last_failure = now;
+
+            let Some(exceeded) = entry.failures.checked_sub(self.config.free_attempts) else {
+                continue;
+            };
+            let secs = self
+                .config
+                .base_lockout_seconds
+                .saturating_mul(1 << exceeded.min(32))
+                .min(self.config.max_lockout_seconds);
+            if secs > 0 {
+                entry.locked_until = now + secs;
+                warn!(
+                    "{} locked out for {}s after {} failures",
+                    key, secs, entry.failures
+                );
+                lockout = lockout.max(Some(secs));
+            }
+        }
+        lockout
+    }
+
+    /// Drop stale entries, or the least recently failed one, when the tracker is full.
+    fn make_room(&self, attempts: &mut HashMap<String, Attempts>, now: u64) {
+        if attempts.len() < self.config.max_entries {
+            return;
+        }
+        let max_lockout = self.config.max_lockout_seconds;
+        attempts.retain(|_, a| now.saturating_sub(a.last_failure) <= max_lockout);
+        if attempts.len() < self.config.max_entries {
+            return;
+        }
+        let oldest = attempts
+            .iter()
+            .min_by_key(|(_, a)| a.last_failure)
+            .map(|(k, _)| k.clone());
+        if let Some(key) = oldest {
+            attempts.remove(&key);
+        }
+    }
+}
+
+impl Default for ThrottleConfig {
+    fn default() -> Self {
+        Self {
+            free_attempts: default_free_attempts(),
+            base_lockout_seconds: default_base_lockout(),
+            max_lockout_seconds: default_max_lockout(),
+            max_entries: default_max_entries(),
+        }
+    }
+}
+
+fn keys(username: &str, ip: Option<IpAddr>) -> Vec<String> {
+    let mut keys = vec![user_key(username)];
+    keys.extend(ip.map(|ip| format!("ip:{}", ip)));
+    keys
+}
+
+fn user_key(username: &str) -> String {
+    format!("user:{}", username)
+}
+
+fn default_free_attempts() -> u32 {
+    5
+}
+
+fn default_base_lockout() -> u64 {
+    1
+}
+
+fn default_max_lockout() -> u64 {
+    15 * 60
+}
+
+fn default_max_entries() -> usize {
+    10_000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker(max_entries: usize) -> AttemptTracker {
+        AttemptTracker::new(ThrottleConfig {
+            free_attempts: 2,
+            base_lockout_seconds: 10,
+            max_lockout_seconds: 100,
+            max_entries,
+        })
+    }
+
+    #[test]
+    fn lockout_should_grow_exponentially() {
+        let tracker = tracker(10);
+        let keys = keys("alice", None);
+        assert_eq!(tracker.failed_at(&keys, 0), None);
+        assert_eq!(tracker.failed_at(&keys, 0), Some(10));
+        assert_eq!(tracker.locked_at(&keys, 5), Some(5));
+        assert_eq!(tracker.failed_at(&keys, 10), Some(20));
+        assert_eq!(tracker.failed_at(&keys, 30), Some(40));
+        assert_eq!(tracker.failed_at(&keys, 70), Some(80));
+        assert_eq!(tracker.failed_at(&keys, 150), Some(100));
+        assert_eq!(tracker.locked_at(&keys, 250), None);
+
+        tracker.succeeded("alice");
+        assert_eq!(tracker.failed_at(&keys, 250), None);
+    }
+
+    #[test]
+    fn ip_should_be_locked_across_usernames() {
+        let tracker = tracker(10);
+        let ip = Some("10.0.0.1".parse().unwrap());
+        for (i, username) in ["alice", "bob", "carol"].iter().enumerate() {
+            tracker.failed_at(&keys(username, ip), i as u64);
+        }
+        assert!(tracker.locked_at(&keys("dave", ip), 2).is_some());
+        assert!(tracker.locked_at(&keys("dave", None), 2).is_none());
+    }
+
+    #[test]
+    fn tracker_should_be_bounded() {
+        let tracker = tracker(2);
+        for (i, username) in ["alice", "bob", "carol"].iter().enumerate() {
+            tracker.failed_at(&keys(username, None), i as u64);
+        }
+        let attempts = tracker.attempts.lock().unwrap();
+        assert_eq!(attempts.len(), 2);
+        assert!(!attempts.contains_key("user:alice"));
+    }
+}
@@ -0,0 +1,92 @@
+use super::{AuthError, Result};
+use crate::store;
+use jwt_simple::prelude::*;
+use std::collections::HashSet;
+
+const DEFAULT_CLOCK_SKEW_SECONDS: u64 = 60;
+/// `sub` of our access tokens, tokens of other purposes have their own.
+pub(super) const ACCESS_TOKEN_SUBJECT: &str = "auth";
+
+/// Claims a token must satisfy besides a valid signature and expiry.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValidationConfig {
+    /// accepted `iss` values, defaults to the server name
+    #[serde(default)]
+    pub allowed_issuers: Option<HashSet<String>>,
+    /// audience that must be present in `aud`
+    #[serde(default)]
+    pub required_audience: Option<String>,
+    /// required `sub` value, defaults to the one of access tokens
+    #[serde(default = "default_required_subject")]
+    pub required_subject: Option<String>,
+    /// tolerated clock difference with the token issuer
+    #[serde(default = "default_clock_skew")]
+    pub clock_skew_seconds: u64,
+    /// reject tokens issued longer ago than this, regardless of their expiry
+    #[serde(default)]
+    pub max_token_age_seconds: Option<u64>,
+}
+
+impl ValidationConfig {
+    pub(super) fn options(&self) -> VerificationOptions {
+        VerificationOptions {
+            time_tolerance: Some(Duration::from_secs(self.clock_skew_seconds)),
+            ..Default::default()
+        }
+    }
+
+    /// Validate registered claims of a token, `issuer` is accepted if no allowed issuers are configured.
+    pub(super) fn validate<C>(&self, claims: &JWTClaims<C>, issuer: &str) -> Result<()> {
+        let iss = claims.issuer.as_deref().unwrap_or_default();
+        let allowed = match &self.allowed_issuers {
+            Some(issuers) => issuers.contains(iss),
+            None => iss == issuer,
+        };
+        if !allowed {
+            return Err(AuthError::IssuerNotAllowed(iss.to_string()));
+        }
+
+        if let Some(audience) = &self.required_audience {
+            let audiences = claims.audiences.clone().map(|a| a.into_set());
+            if !audiences.map_or(false, |a| a.contains(audience)) {
+                return Err(AuthError::AudienceMismatch(audience.clone()));
+            }
+        }
+
+        if let Some(subject) = &self.required_subject {
+            if claims.subject.as_ref() != Some(subject) {
+                return Err(AuthError::SubjectMismatch(subject.clone()));
+            }
+        }
+
+        if let Some(max_age) = self.max_token_age_seconds {
+            let issued_at = claims.issued_at.map(|t| t.as_secs()).unwrap_or_default();
+            let max_age = max_age + self.clock_skew_seconds;
+            if store::now().saturating_sub(issued_at) > max_age {
+                return Err(AuthError::TokenTooOld);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        Self {
+            allowed_issuers: None,
+            required_audience: None,
+            required_subject: default_required_subject(),
+            clock_skew_seconds: DEFAULT_CLOCK_SKEW_SECONDS,
+            max_token_age_seconds: None,
+        }
+    }
+}
+
+fn default_required_subject() -> Option<String> {
+    Some(ACCESS_TOKEN_SUBJECT.to_string())
+}
+
+fn default_clock_skew() -> u64 {
+    DEFAULT_CLOCK_SKEW_SECONDS
+}
@@ -0,0 +1,72 @@
+//! Well-known endpoints for other services to verify tokens issued by us.
+
+use crate::{auth::Jwks, oauth, AppState};
+use axum::{
+    extract::{Host, State},
+    http::header,
+    response::IntoResponse,
+    Json,
+};
+use serde::Serialize;
+use std::sync::Arc;
+
+pub const JWKS_PATH: &str = "/.well-known/jwks.json";
+pub const OPENID_CONFIGURATION_PATH: &str = "/.well-known/openid-configuration";
+
+/// Keys rotate rarely, but verifiers should not cache them for long.
+const CACHE_CONTROL: &str = "public, max-age=300";
+/// Urls built from the `Host` of the request must not be served to other clients by a cache.
+const HOST_CACHE_CONTROL: &str = "no-store";
+
+/// A subset of the OpenID provider metadata, enough for verifiers to discover our keys.
+#[derive(Debug, Serialize)]
+pub struct OpenIdConfiguration {
+    pub issuer: String,
+    pub jwks_uri: String,
+    pub token_endpoint: String,
+    pub grant_types_supported: Vec<String>,
+    pub token_endpoint_auth_methods_supported: Vec<String>,
+    pub response_types_supported: Vec<String>,
+    pub subject_types_supported: Vec<String>,
+    pub id_token_signing_alg_values_supported: Vec<String>,
+}
+
+pub async fn jwks(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let jwks: Jwks = state.verifier.jwks();
+    ([(header::CACHE_CONTROL, CACHE_CONTROL)], Json(jwks))
+}
+
+pub async fn openid_configuration(
+    Host(host): Host,
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    let base_url = state.public_url(&host);
+    let mut algs: Vec<_> = state
+        .verifier
+        .jwks()
+        .keys
+        .into_iter()
+        .map(|k| k.alg)
+        .collect();
+    algs.sort();
+    algs.dedup();
+
+    let config = OpenIdConfiguration {
+        issuer: state.verifier.provider().to_string(),
+        jwks_uri: format!("{}{}", base_url, JWKS_PATH),
+        token_endpoint: format!("{}{}", base_url, oauth::TOKEN_PATH),
+        grant_types_supported: vec!["client_credentials".to_string()],
+        token_endpoint_auth_methods_supported: vec![
+            "client_secret_basic".to_string(),
+            "client_secret_post".to_string(),
+        ],
+        response_types_supported: vec!["token".to_string()],
+        subject_types_supported: vec!["public".to_string()],
+        id_token_signing_alg_values_supported: algs,
+    };
+    let cache_control = match state.config.public_url {
+        Some(_) => CACHE_CONTROL,
+        None => HOST_CACHE_CONTROL,
+    };
+    ([(header::CACHE_CONTROL, cache_control)], Json(config))
+}
@@ -1,3 +1,24 @@
+use crate::{audit::AuditError, auth::AuthError, model::ModelError, store::StoreError};
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Errors that prevent the server from starting.
+#[derive(Debug, Error)]
+pub enum AppError {
+    #[error("failed to read config {}: {1}", .0.display())]
+    ConfigIo(PathBuf, std::io::Error),
+    #[error("invalid config {}: {1}", .0.display())]
+    ConfigParse(PathBuf, serde_json::Error),
+    #[error("auth setup failed: {0}")]
+    Auth(#[from] AuthError),
+    #[error("store setup failed: {0}")]
+    Store(#[from] StoreError),
+    #[error("invalid smithy model: {0}")]
+    Model(#[from] ModelError),
+    #[error("audit setup failed: {0}")]
+    Audit(#[from] AuditError),
+}
+
 #[macro_export]
 macro_rules! err {
     ($ty:ident, $msg:expr) => {
@@ -82,6 +103,22 @@ macro_rules! forbidden {
     };
 }
 
+#[macro_export]
+macro_rules! throttled {
+    ($retry_after:expr, $msg:expr) => {
+        return Err(echo_server_sdk::error::ThrottlingError {
+            message: $msg.to_string(),
+            retry_after_seconds: Some(i32::try_from($retry_after).unwrap_or(i32::MAX)),
+        }.into())
+    };
+    ($retry_after:expr, $msg:expr, $($param:expr),*) => {
+        return Err(echo_server_sdk::error::ThrottlingError {
+            message: format!($msg, $($param),*),
+            retry_after_seconds: Some(i32::try_from($retry_after).unwrap_or(i32::MAX)),
+        }.into())
+    };
+}
+
 #[macro_export]
 macro_rules! unauthorized {
     ( $msg:expr) => {
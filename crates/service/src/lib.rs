@@ -1,60 +1,139 @@
 mod api;
+mod audit;
 mod auth;
+mod discovery;
 mod error;
 mod middleware;
+mod model;
+mod notify;
+mod oauth;
+mod store;
+mod tls;
 
-use auth::{AuthConfig, AuthSigner, AuthVerifier};
+pub use audit::{
+    verify_chain, AuditConfig, AuditEntry, AuditError, AuditEvent, AuditRecord, AuditSink,
+    FileAuditSink, MemoryAuditSink, Outcome,
+};
+pub use error::AppError;
+pub use notify::{Notification, Notifier, NotifierConfig, NotifyError};
+pub use tls::{serve_tls, TlsConfig, TlsError};
+
+use auth::{
+    ApiKeys, AttemptTracker, AuthConfig, AuthSigner, AuthVerifier, ExternalIssuer, Identity,
+    KeyRing, RefreshTokens, Sessions,
+};
 use aws_smithy_http_server::{
     plugin::IdentityPlugin, request::request_id::ServerRequestIdProviderLayer, AddExtensionLayer,
 };
 use axum::{
     http::{HeaderName, Method},
+    middleware::from_fn,
     response::Html,
-    routing::get,
-    Router,
+    routing::{get, post},
+    Extension, Router,
 };
 use axum_swagger_ui::swagger_ui;
 use derive_more::Debug;
 use echo_server_sdk::{EchoService, EchoServiceConfig};
-use middleware::{BearerTokenProviderLayer, ServerTimingLayer};
+use middleware::{AuthPlugin, ScopeAuthPlugin, ServerTimingLayer, SessionCookiePlugin};
+use model::SmithyModel;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::{fs, path::Path, sync::Arc, time::Duration};
+use store::{StoreConfig, UserStore};
+use tls::ClientCertificate;
 use tower_http::cors::{Any, CorsLayer};
+use tracing::warn;
+
+/// Header of the id of a request, which is in its audit entries too.
+const REQUEST_ID_HEADER: &str = "x-request-id";
 
 #[derive(Debug)]
 pub struct AppState {
-    #[allow(dead_code)]
     config: AppConfig,
     pub(crate) verifier: AuthVerifier,
-    #[allow(dead_code)]
     pub(crate) signer: AuthSigner,
+    pub(crate) users: Arc<dyn UserStore>,
+    pub(crate) refresh_tokens: RefreshTokens,
+    pub(crate) api_keys: ApiKeys,
+    pub(crate) sessions: Sessions,
+    pub(crate) signin_attempts: AttemptTracker,
+    pub(crate) notifier: Arc<dyn Notifier>,
+    audit_sink: Option<Arc<dyn AuditSink>>,
+    pub(crate) keyring: Arc<KeyRing>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppConfig {
     pub server_name: String,
     pub port: u16,
+    /// public base url of the server, e.g. `https://auth.example.com`, defaults to `http://<host>`
+    /// whose discovery documents are not cacheable
+    #[serde(default)]
+    pub public_url: Option<String>,
+    /// take the client ip from the last `X-Forwarded-For` address, only enable behind a proxy that
+    /// appends it
+    #[serde(default)]
+    pub trust_forwarded_for: bool,
     pub auth: AuthConfig,
+    #[serde(default)]
+    pub store: StoreConfig,
+    /// delivery of password reset tokens
+    #[serde(default)]
+    pub notifier: NotifierConfig,
+    /// serve over TLS, optionally authenticating clients by their certificate
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    /// audit log of security events, disabled if absent
+    #[serde(default)]
+    pub audit: Option<AuditConfig>,
 }
 
-pub async fn get_router(conf: AppConfig) -> Router {
+pub async fn get_router(conf: AppConfig) -> Result<Router, AppError> {
     // make name with static lifetime
     let name = Box::leak(Box::new(conf.server_name.clone()));
 
-    let state = Arc::new(AppState::new(conf));
+    let rotation_hours = conf.auth.rotation_hours;
+    let state = Arc::new(AppState::try_new(conf)?);
+    if let Some(hours) = rotation_hours {
+        state
+            .keyring
+            .clone()
+            .spawn_rotation(Duration::from_secs(hours * 3600));
+    }
+    let model = include_str!("../../../smithy/build/smithy/source/model/model.json");
+    let model = Arc::new(SmithyModel::try_new(model)?);
 
     let config = EchoServiceConfig::builder()
         // IdentityPlugin is a plugin that adds a middleware to the service, it just shows how to use plugins
         .http_plugin(IdentityPlugin)
+        // set the session cookie of browser clients according to the `@sessionCookie` trait
+        .http_plugin(SessionCookiePlugin::new(model.clone()))
+        // authenticate callers per operation according to the `@auth` trait in the model
+        .http_plugin(AuthPlugin::new(model.clone()))
+        // then check the scopes of the caller according to the `@requiredScopes` trait
+        .http_plugin(ScopeAuthPlugin::new(model))
         .layer(AddExtensionLayer::new(state.clone()))
-        .layer(BearerTokenProviderLayer::new())
         .layer(ServerRequestIdProviderLayer::new_with_response_header(
-            HeaderName::from_static("x-request-id"),
+            HeaderName::from_static(REQUEST_ID_HEADER),
         ))
         .build();
     let api = EchoService::builder(config)
         .echo_message(api::echo_message)
         .signin(api::signin)
+        .signup(api::signup)
+        .refresh_token(api::refresh_token)
+        .signout(api::signout)
+        .create_api_key(api::create_api_key)
+        .list_api_keys(api::list_api_keys)
+        .revoke_api_key(api::revoke_api_key)
+        .list_sessions(api::list_sessions)
+        .revoke_session(api::revoke_session)
+        .introspect_token(api::introspect_token)
+        .change_password(api::change_password)
+        .request_password_reset(api::request_password_reset)
+        .confirm_password_reset(api::confirm_password_reset)
+        .enroll_mfa(api::enroll_mfa)
+        .confirm_mfa(api::confirm_mfa)
         .build()
         .expect("failed to build an instance of Echo Service");
 
@@ -77,13 +156,25 @@ pub async fn get_router(conf: AppConfig) -> Router {
         .allow_origin(Any)
         .allow_private_network(true);
 
-    Router::new()
+    let router = Router::new()
         .route("/swagger", get(|| async { Html(swagger_ui(doc_url)) }))
         .route(doc_url, get(move || async move { doc }))
+        .route(discovery::JWKS_PATH, get(discovery::jwks))
+        .route(
+            discovery::OPENID_CONFIGURATION_PATH,
+            get(discovery::openid_configuration),
+        )
+        .route(
+            oauth::TOKEN_PATH,
+            post(oauth::token).layer(from_fn(oauth::with_request_id)),
+        )
         .nest_service("/api/", api)
+        // the state in the extensions too, for the client ip of the routes beside the api
+        .layer(Extension(state.clone()))
         .layer(ServerTimingLayer::new(name))
         .layer(cors)
-        .with_state(state)
+        .with_state(state);
+    Ok(router)
 }
 
 impl Default for AppConfig {
@@ -91,19 +182,99 @@ impl Default for AppConfig {
         Self {
             server_name: "echo-service".to_string(),
             port: 3000,
+            public_url: None,
+            trust_forwarded_for: false,
             auth: AuthConfig::default(),
+            store: StoreConfig::default(),
+            notifier: NotifierConfig::default(),
+            tls: None,
+            audit: None,
         }
     }
 }
 
+impl AppConfig {
+    /// Load the config from a json file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, AppError> {
+        let path = path.as_ref();
+        let data = fs::read(path).map_err(|e| AppError::ConfigIo(path.to_path_buf(), e))?;
+        serde_json::from_slice(&data).map_err(|e| AppError::ConfigParse(path.to_path_buf(), e))
+    }
+}
+
 impl AppState {
-    pub fn new(config: AppConfig) -> Self {
-        let signer = AuthSigner::try_new(&config.server_name, &config.auth.sk).unwrap();
-        let verifier = AuthVerifier::try_new(&config.server_name, &config.auth.pk).unwrap();
-        Self {
+    /// Public base url without trailing slash, falls back to the `Host` of the request.
+    pub(crate) fn public_url(&self, host: &str) -> String {
+        match &self.config.public_url {
+            Some(url) => url.trim_end_matches('/').to_string(),
+            None => format!("http://{}", host),
+        }
+    }
+
+    /// Identity of a client certificate verified during the TLS handshake.
+    pub(crate) fn certificate_identity(&self, cert: &ClientCertificate) -> Option<Identity> {
+        let tls = self.config.tls.as_ref()?;
+        tls.identity(cert, &self.config.auth.roles)
+    }
+
+    /// Record a security event in the audit log, failures are logged so the request still succeeds.
+    pub(crate) fn audit(&self, record: AuditRecord) {
+        let Some(sink) = &self.audit_sink else {
+            return;
+        };
+        if let Err(e) = sink.record(record) {
+            warn!("failed to record audit entry: {}", e);
+        }
+    }
+
+    pub fn try_new(config: AppConfig) -> Result<Self, AppError> {
+        let keyring = Arc::new(config.auth.keyring()?);
+        let token = &config.auth.token;
+        let issuer = token.issuer.as_ref().unwrap_or(&config.server_name);
+        let signer = AuthSigner::new(issuer, keyring.clone(), token.clone());
+        let revocations = config.store.revocation_store()?;
+        let external_issuers = config
+            .auth
+            .external_issuers
+            .iter()
+            .map(|c| {
+                ExternalIssuer::new(
+                    c.clone(),
+                    config.auth.roles.clone(),
+                    &config.auth.validation,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let verifier = AuthVerifier::new(
+            issuer,
+            keyring.clone(),
+            revocations,
+            config.auth.validation.clone(),
+        )
+        .with_external_issuers(external_issuers);
+        let users = config.store.user_store()?;
+        let refresh_tokens = RefreshTokens::new(config.store.refresh_token_store()?);
+        let api_keys = ApiKeys::new(
+            config.store.api_key_store()?,
+            users.clone(),
+            config.auth.roles.clone(),
+        );
+        let sessions = Sessions::new(config.store.session_store()?);
+        let signin_attempts = AttemptTracker::new(config.auth.throttle.clone());
+        let notifier = config.notifier.notifier();
+        let audit_sink = config.audit.as_ref().map(AuditConfig::sink).transpose()?;
+        Ok(Self {
             config,
             verifier,
             signer,
-        }
+            users,
+            refresh_tokens,
+            api_keys,
+            sessions,
+            signin_attempts,
+            notifier,
+            audit_sink,
+            keyring,
+        })
     }
 }
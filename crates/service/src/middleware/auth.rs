@@ -0,0 +1,465 @@
+use crate::{
+    audit::{self, AuditEvent},
+    auth::{AuthError, ExternalIssuer, Identity},
+    model::{
+        ApiKeyAuth, ApiKeyLocation, SmithyModel, HTTP_API_KEY_AUTH, HTTP_BEARER_AUTH, MTLS_AUTH,
+    },
+    tls::ClientCertificate,
+    AppState,
+};
+use aws_smithy_http_server::{
+    body::{to_boxed, BoxBody},
+    operation::OperationShape,
+    plugin::{HttpMarker, Plugin},
+    service::ServiceShape,
+};
+use axum::http::{header, Request, Response, StatusCode, Uri};
+use echo_server_sdk::server::response::IntoResponse;
+use jwt_simple::prelude::Token;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use thiserror::Error;
+use tower::Service;
+use tracing::{debug, warn};
+
+/// The request could not be authenticated with any auth scheme of the operation.
+#[non_exhaustive]
+#[derive(Debug, Error)]
+pub enum AuthenticationError {
+    #[error("no credentials are present in the request")]
+    Missing,
+    #[error("the credentials are not valid")]
+    Invalid,
+    #[error("the bearer token is malformed")]
+    Malformed,
+    #[error("the CSRF token does not match the session")]
+    CsrfMismatch,
+    #[error("{0}")]
+    Rejected(#[from] AuthError),
+}
+
+/// Auth schemes of the model supported by [`AuthProvider`].
+#[derive(Debug, Clone)]
+enum AuthScheme {
+    Bearer,
+    ApiKey(ApiKeyAuth),
+    MutualTls,
+    /// the bearer token in the session cookie, in session mode
+    SessionCookie,
+}
+
+#[derive(Clone)]
+pub struct AuthProvider<S> {
+    inner: S,
+    schemes: Arc<[AuthScheme]>,
+    required: bool,
+}
+
+/// A plugin that authenticates callers of operations, based on the `@auth` trait of the operation.
+///
+/// Schemes are tried in the order of the `@auth` list, the first scheme whose credentials are
+/// present in the request decides.
+#[derive(Debug, Clone)]
+pub struct AuthPlugin {
+    model: Arc<SmithyModel>,
+}
+
+impl AuthPlugin {
+    pub fn new(model: Arc<SmithyModel>) -> Self {
+        Self { model }
+    }
+}
+
+impl<Ser, Op, T> Plugin<Ser, Op, T> for AuthPlugin
+where
+    Ser: ServiceShape,
+    Op: OperationShape,
+{
+    type Output = AuthProvider<T>;
+
+    fn apply(&self, inner: T) -> Self::Output {
+        let service = Ser::ID.absolute();
+        let mut ids = self.model.auth_schemes(service, Op::ID.absolute());
+        let required = !ids.is_empty();
+        if !required {
+            // anonymous operations still get the identity when valid credentials are presented
+            ids = self.model.service_auth_schemes(service);
+        }
+
+        let mut schemes: Vec<_> = ids
+            .iter()
+            .filter_map(|id| match id.as_str() {
+                HTTP_BEARER_AUTH => Some(AuthScheme::Bearer),
+                HTTP_API_KEY_AUTH => self.model.api_key_auth(service).map(AuthScheme::ApiKey),
+                MTLS_AUTH => Some(AuthScheme::MutualTls),
+                _ => {
+                    warn!("auth scheme {} is not supported", id);
+                    None
+                }
+            })
+            .collect();
+        if schemes.iter().any(|s| matches!(s, AuthScheme::Bearer)) {
+            // browsers may send the bearer token in the session cookie instead of the header
+            schemes.push(AuthScheme::SessionCookie);
+        }
+        AuthProvider {
+            inner,
+            schemes: schemes.into(),
+            required,
+        }
+    }
+}
+
+impl HttpMarker for AuthPlugin {}
+
+impl<Body, S> Service<Request<Body>> for AuthProvider<S>
+where
+    Body: Send + 'static,
+    S: Service<Request<Body>, Response = Response<BoxBody>> + Clone + Send + 'static,
+    S::Future: std::marker::Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Send + Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        if let Some(issuer) = self.stale_issuer(&req) {
+            // the keys of the issuer are fetched before the token is verified
+            let clone = self.inner.clone();
+            let mut inner = std::mem::replace(&mut self.inner, clone);
+            let provider = AuthProvider {
+                inner: (),
+                schemes: self.schemes.clone(),
+                required: self.required,
+            };
+            return Box::pin(async move {
+                if let Err(e) = issuer.refresh().await {
+                    warn!("failed to refresh keys of {}: {}", issuer.issuer(), e);
+                }
+                match provider.process(req) {
+                    Ok(req) => inner.call(req).await,
+                    Err(e) => Ok(<AuthenticationError as IntoResponse<()>>::into_response(e)),
+                }
+            });
+        }
+        match self.process(req) {
+            Ok(req) => {
+                let fut = self.inner.call(req);
+                Box::pin(async move {
+                    let res = fut.await?;
+                    Ok(res)
+                })
+            }
+            Err(e) => {
+                let res = <AuthenticationError as IntoResponse<()>>::into_response(e);
+                Box::pin(async move { Ok(res) })
+            }
+        }
+    }
+}
+
+impl<S> AuthProvider<S> {
+    fn process<Body>(&self, mut req: Request<Body>) -> Result<Request<Body>, AuthenticationError> {
+        match self.authenticate(&req) {
+            Ok(identity) => {
+                req.extensions_mut().insert(identity);
+                Ok(req)
+            }
+            Err(e) if self.required => {
+                audit::record_denied(&req, AuditEvent::AuthenticationDenied, None, &e);
+                Err(e)
+            }
+            Err(AuthenticationError::Missing) => Ok(req),
+            Err(e) => {
+                debug!("ignored credentials on anonymous operation: {}", e);
+                Ok(req)
+            }
+        }
+    }
+
+    /// External issuer of the bearer token of the request, if its keys must be fetched first.
+    fn stale_issuer<Body>(&self, req: &Request<Body>) -> Option<Arc<ExternalIssuer>> {
+        let state = req.extensions().get::<Arc<AppState>>()?;
+        let bearer = self
+            .schemes
+            .iter()
+            .find(|s| matches!(s, AuthScheme::Bearer))?;
+        let token = bearer.credential(req).ok()??;
+        state.verifier.stale_issuer(token)
+    }
+
+    fn authenticate<Body>(&self, req: &Request<Body>) -> Result<Identity, AuthenticationError> {
+        let state = req.extensions().get::<Arc<AppState>>().unwrap();
+        for scheme in self.schemes.iter() {
+            if let Some(identity) = scheme.authenticate(state, req) {
+                return identity.map_err(|e| {
+                    warn!("credentials rejected: {}", e);
+                    e
+                });
+            }
+        }
+        Err(AuthenticationError::Missing)
+    }
+}
+
+impl AuthScheme {
+    /// Authenticate the request with this scheme, `None` if the client does not use it.
+    fn authenticate<Body>(
+        &self,
+        state: &AppState,
+        req: &Request<Body>,
+    ) -> Option<Result<Identity, AuthenticationError>> {
+        if let Self::MutualTls = self {
+            // the certificate is verified by the TLS handshake already
+            let cert = req.extensions().get::<ClientCertificate>()?;
+            let identity = state.certificate_identity(cert);
+            return Some(identity.ok_or(AuthenticationError::Invalid));
+        }
+        if let Self::SessionCookie = self {
+            let session = state.config.auth.session.as_ref()?;
+            let token = session.token(req.headers())?;
+            if !session.check_csrf(req.method(), req.headers()) {
+                return Some(Err(AuthenticationError::CsrfMismatch));
+            }
+            if Token::decode_metadata(token).is_err() {
+                return Some(Err(AuthenticationError::Malformed));
+            }
+            let identity = state
+                .verifier
+                .verify(token)
+                .map(Identity::from)
+                .and_then(|identity| state.sessions.check(identity));
+            return Some(identity.map_err(Into::into));
+        }
+
+        let credential = match self.credential(req).transpose()? {
+            Ok(credential) => credential,
+            Err(e) => return Some(Err(e)),
+        };
+        if matches!(self, Self::Bearer) && Token::decode_metadata(credential).is_err() {
+            return Some(Err(AuthenticationError::Malformed));
+        }
+        let identity = match self {
+            Self::ApiKey(_) => state.api_keys.verify(credential).map(Identity::from),
+            _ => state
+                .verifier
+                .authenticate(credential)
+                .and_then(|identity| state.sessions.check(identity)),
+        };
+        Some(identity.map_err(Into::into))
+    }
+
+    /// Credential of the scheme in the request, `None` if the client does not use this scheme.
+    fn credential<'a, Body>(
+        &self,
+        req: &'a Request<Body>,
+    ) -> Result<Option<&'a str>, AuthenticationError> {
+        let (name, scheme) = match self {
+            Self::Bearer => (header::AUTHORIZATION.as_str(), Some("Bearer")),
+            Self::ApiKey(auth) => match auth.location {
+                ApiKeyLocation::Header => (auth.name.as_str(), auth.scheme.as_deref()),
+                ApiKeyLocation::Query => return Ok(query_param(req.uri(), &auth.name)),
+            },
+            Self::MutualTls | Self::SessionCookie => return Ok(None),
+        };
+        let Some(value) = req.headers().get(name) else {
+            return Ok(None);
+        };
+        let value = value.to_str().map_err(|_| match self {
+            Self::Bearer => AuthenticationError::Malformed,
+            _ => AuthenticationError::Invalid,
+        })?;
+        Ok(match scheme {
+            Some(scheme) => match value.split_once(' ') {
+                Some((s, credential)) if s.eq_ignore_ascii_case(scheme) => Some(credential.trim()),
+                // the header may carry the credential of another scheme, e.g. `Authorization`
+                _ => None,
+            },
+            None => Some(value.trim()),
+        })
+    }
+}
+
+fn query_param<'a>(uri: &'a Uri, name: &str) -> Option<&'a str> {
+    uri.query()?
+        .split('&')
+        .filter_map(|p| p.split_once('='))
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| v)
+}
+
+impl AuthenticationError {
+    /// Message of the response, fixed so that the details of a rejection stay in the logs.
+    fn message(&self) -> &'static str {
+        match self {
+            Self::Missing => "no credentials are present in the request",
+            Self::Invalid | Self::Rejected(AuthError::InvalidApiKey) => {
+                "the credentials are not valid"
+            }
+            Self::Malformed => "the bearer token is malformed",
+            Self::CsrfMismatch => "the CSRF token does not match the session",
+            Self::Rejected(AuthError::ApiKeyExpired) => "the api key has expired",
+            Self::Rejected(AuthError::TokenExpired) => "the token has expired",
+            Self::Rejected(AuthError::TokenRevoked | AuthError::InvalidSession) => {
+                "the token has been revoked"
+            }
+            Self::Rejected(_) => "the token is invalid",
+        }
+    }
+
+    /// `WWW-Authenticate` challenge of RFC 6750, telling clients whether to get a new token.
+    ///
+    /// Requests without a bearer token get no error code, as the RFC requires.
+    fn challenge(&self) -> Option<String> {
+        let description = match self {
+            Self::CsrfMismatch => return None,
+            Self::Missing
+            | Self::Invalid
+            | Self::Rejected(AuthError::InvalidApiKey | AuthError::ApiKeyExpired) => {
+                return Some("Bearer".to_string())
+            }
+            Self::Malformed => "the token is malformed",
+            Self::Rejected(AuthError::TokenExpired) => "the token has expired",
+            Self::Rejected(AuthError::TokenRevoked | AuthError::InvalidSession) => {
+                "the token has been revoked"
+            }
+            Self::Rejected(_) => "the token is invalid",
+        };
+        Some(format!(
+            "Bearer error=\"invalid_token\", error_description=\"{}\"",
+            description
+        ))
+    }
+}
+
+/// The modeled `UnauthorizedError`, or `ForbiddenError` on a CSRF mismatch, as rendered by
+/// the restJson1 protocol so that clients can deserialize it. Failures of the server, e.g. of
+/// the store, are a `ServerError` so that clients don't retry with new credentials.
+impl<Protocol> IntoResponse<Protocol> for AuthenticationError {
+    fn into_response(self) -> Response<BoxBody> {
+        let (status, error_type) = match &self {
+            Self::Rejected(e) if e.is_internal() => {
+                let body = serde_json::json!({
+                    "code": "unknown",
+                    "message": "internal server error",
+                });
+                return error_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "ServerError",
+                    body,
+                    None,
+                );
+            }
+            Self::CsrfMismatch => (StatusCode::FORBIDDEN, "ForbiddenError"),
+            _ => (StatusCode::UNAUTHORIZED, "UnauthorizedError"),
+        };
+        let body = serde_json::json!({ "message": self.message() });
+        error_response(status, error_type, body, self.challenge())
+    }
+}
+
+/// A modeled error as rendered by the restJson1 protocol, with an optional `WWW-Authenticate`
+/// challenge.
+pub(super) fn error_response(
+    status: StatusCode,
+    error_type: &str,
+    body: serde_json::Value,
+    challenge: Option<String>,
+) -> Response<BoxBody> {
+    let mut res = Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header("x-amzn-errortype", error_type);
+    if let Some(challenge) = challenge {
+        res = res.header(header::WWW_AUTHENTICATE, challenge);
+    }
+    res.body(to_boxed(body.to_string())).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::StoreError;
+
+    #[test]
+    fn credential_should_match_scheme_of_header() {
+        let req = Request::builder()
+            .uri("/echo?api_key=query-key")
+            .header("Authorization", "Bearer token")
+            .header("X-Api-Key", "header-key")
+            .body(())
+            .unwrap();
+        let api_key = |location, name: &str| {
+            AuthScheme::ApiKey(ApiKeyAuth {
+                name: name.to_string(),
+                location,
+                scheme: None,
+            })
+        };
+
+        let credential = |scheme: AuthScheme| scheme.credential(&req).unwrap();
+        assert_eq!(credential(AuthScheme::Bearer), Some("token"));
+        assert_eq!(
+            credential(api_key(ApiKeyLocation::Header, "X-Api-Key")),
+            Some("header-key")
+        );
+        assert_eq!(
+            credential(api_key(ApiKeyLocation::Query, "api_key")),
+            Some("query-key")
+        );
+        let prefixed = AuthScheme::ApiKey(ApiKeyAuth {
+            name: "Authorization".to_string(),
+            location: ApiKeyLocation::Header,
+            scheme: Some("ApiKey".to_string()),
+        });
+        assert_eq!(credential(prefixed), None);
+    }
+
+    #[test]
+    fn challenge_should_tell_expired_from_malformed_tokens() {
+        let response = |e: AuthenticationError| {
+            let res = <AuthenticationError as IntoResponse<()>>::into_response(e);
+            let get = |name: &str| {
+                res.headers()
+                    .get(name)
+                    .map(|v| v.to_str().unwrap().to_string())
+            };
+            (
+                res.status(),
+                get("x-amzn-errortype"),
+                get(header::WWW_AUTHENTICATE.as_str()),
+            )
+        };
+
+        let (status, error_type, challenge) = response(AuthenticationError::Missing);
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(error_type.as_deref(), Some("UnauthorizedError"));
+        assert_eq!(challenge.as_deref(), Some("Bearer"));
+
+        let (_, _, challenge) = response(AuthenticationError::Malformed);
+        assert!(challenge.unwrap().contains("the token is malformed"));
+        let (_, _, challenge) = response(AuthError::TokenExpired.into());
+        assert_eq!(
+            challenge.as_deref(),
+            Some(r#"Bearer error="invalid_token", error_description="the token has expired""#)
+        );
+
+        let (status, error_type, challenge) = response(AuthenticationError::CsrfMismatch);
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(error_type.as_deref(), Some("ForbiddenError"));
+        assert!(challenge.is_none());
+
+        let store_error =
+            StoreError::Io(std::io::Error::new(std::io::ErrorKind::Other, "disk full"));
+        let (status, error_type, challenge) = response(AuthError::Store(store_error).into());
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(error_type.as_deref(), Some("ServerError"));
+        assert!(challenge.is_none());
+    }
+}
@@ -0,0 +1,73 @@
+use crate::AppState;
+use aws_smithy_http_server::request::FromParts;
+use axum::{
+    extract::ConnectInfo,
+    http::{request::Parts, Extensions, HeaderMap},
+};
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+/// Ip address of the client, `None` if the server is not started with connect info.
+///
+/// The last address of `X-Forwarded-For` is used if [`AppConfig::trust_forwarded_for`](crate::AppConfig)
+/// is set, otherwise the peer address of the connection. The proxy appends the address of its
+/// peer, the addresses before it are sent by the client and can't be trusted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl ClientIp {
+    /// Client ip of a request, also available to middleware without [`Parts`].
+    pub fn resolve(headers: &HeaderMap, extensions: &Extensions) -> Self {
+        let trust_forwarded_for = extensions
+            .get::<Arc<AppState>>()
+            .map_or(false, |s| s.config.trust_forwarded_for);
+        if let (true, Some(ip)) = (trust_forwarded_for, forwarded_for(headers)) {
+            return Self(Some(ip));
+        }
+
+        let peer = extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        Self(peer)
+    }
+}
+
+/// Address appended to `X-Forwarded-For` by the proxy in front of the server.
+fn forwarded_for(headers: &HeaderMap) -> Option<IpAddr> {
+    let last = headers.get_all("x-forwarded-for").iter().last()?;
+    last.to_str().ok()?.rsplit(',').next()?.trim().parse().ok()
+}
+
+impl<P> FromParts<P> for ClientIp {
+    type Rejection = Infallible;
+
+    fn from_parts(parts: &mut Parts) -> Result<Self, Self::Rejection> {
+        Ok(Self::resolve(&parts.headers, &parts.extensions))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spoofed_forwarded_for_should_be_ignored() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            "203.0.113.7, 10.0.0.1, 192.0.2.10".parse().unwrap(),
+        );
+        assert_eq!(forwarded_for(&headers), "192.0.2.10".parse().ok());
+
+        // a second header line is appended by the proxy after the one of the client
+        headers.insert("x-forwarded-for", "203.0.113.7".parse().unwrap());
+        headers.append("x-forwarded-for", "192.0.2.10".parse().unwrap());
+        assert_eq!(forwarded_for(&headers), "192.0.2.10".parse().ok());
+
+        headers.insert("x-forwarded-for", "192.0.2.10, garbage".parse().unwrap());
+        assert_eq!(forwarded_for(&headers), None);
+    }
+}
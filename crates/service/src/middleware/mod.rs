@@ -1,5 +1,13 @@
-mod bearer_auth;
+mod auth;
+mod client_ip;
+mod scope_auth;
 mod server_timing;
+mod session;
+mod user_agent;
 
-pub use bearer_auth::BearerTokenProviderLayer;
+pub use auth::{AuthPlugin, AuthenticationError};
+pub use client_ip::ClientIp;
+pub use scope_auth::ScopeAuthPlugin;
 pub use server_timing::ServerTimingLayer;
+pub use session::{IssuedSession, SessionCookiePlugin};
+pub use user_agent::UserAgent;
@@ -0,0 +1,102 @@
+use super::{auth::error_response, AuthenticationError};
+use crate::{
+    audit::{self, AuditEvent},
+    auth::Identity,
+    model::SmithyModel,
+};
+use aws_smithy_http_server::{
+    body::BoxBody,
+    operation::OperationShape,
+    plugin::{HttpMarker, Plugin},
+};
+use axum::http::{Request, Response, StatusCode};
+use echo_server_sdk::server::response::IntoResponse;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tower::Service;
+use tracing::warn;
+
+#[derive(Clone)]
+pub struct ScopeAuthorizer<S> {
+    inner: S,
+    scopes: Arc<[String]>,
+}
+
+/// A plugin that checks the scopes of the caller against the `@requiredScopes` trait of the operation.
+///
+/// It must run after [`AuthPlugin`](super::AuthPlugin) which provides the [`Identity`].
+#[derive(Debug, Clone)]
+pub struct ScopeAuthPlugin {
+    model: Arc<SmithyModel>,
+}
+
+impl ScopeAuthPlugin {
+    pub fn new(model: Arc<SmithyModel>) -> Self {
+        Self { model }
+    }
+}
+
+impl<Ser, Op, T> Plugin<Ser, Op, T> for ScopeAuthPlugin
+where
+    Op: OperationShape,
+{
+    type Output = ScopeAuthorizer<T>;
+
+    fn apply(&self, inner: T) -> Self::Output {
+        let scopes = self.model.required_scopes(Op::ID.absolute());
+        ScopeAuthorizer {
+            inner,
+            scopes: scopes.into(),
+        }
+    }
+}
+
+impl HttpMarker for ScopeAuthPlugin {}
+
+impl<Body, S> Service<Request<Body>> for ScopeAuthorizer<S>
+where
+    S: Service<Request<Body>, Response = Response<BoxBody>>,
+    S::Future: std::marker::Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Send + Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        if self.scopes.is_empty() {
+            return Box::pin(self.inner.call(req));
+        }
+
+        let Some(identity) = req.extensions().get::<Identity>() else {
+            // only anonymous operations reach here without an identity
+            let e = AuthenticationError::Missing;
+            audit::record_denied(&req, AuditEvent::AuthenticationDenied, None, &e);
+            let res = <AuthenticationError as IntoResponse<()>>::into_response(e);
+            return Box::pin(async move { Ok(res) });
+        };
+        if identity.has_scopes(&self.scopes) {
+            return Box::pin(self.inner.call(req));
+        }
+
+        warn!("{} lacks scopes {:?}", identity.username, self.scopes);
+        let scopes = self.scopes.join(" ");
+        let message = format!("required scopes: {}", scopes);
+        let principal = Some(identity.username.as_str());
+        audit::record_denied(&req, AuditEvent::AuthorizationDenied, principal, &message);
+        // RFC 6750 section 3.1
+        let challenge = format!("Bearer error=\"insufficient_scope\", scope=\"{}\"", scopes);
+        let res = error_response(
+            StatusCode::FORBIDDEN,
+            "ForbiddenError",
+            serde_json::json!({ "message": message }),
+            Some(challenge),
+        );
+        Box::pin(async move { Ok(res) })
+    }
+}
@@ -0,0 +1,150 @@
+use crate::{
+    auth::SessionConfig,
+    model::{SessionCookie, SmithyModel},
+    AppState,
+};
+use aws_smithy_http_server::{
+    body::BoxBody,
+    operation::OperationShape,
+    plugin::{HttpMarker, Plugin},
+    request::FromParts,
+};
+use axum::http::{header, request::Parts, HeaderValue, Request, Response};
+use std::convert::Infallible;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tower::Service;
+use tracing::{debug, warn};
+
+#[derive(Clone)]
+pub struct SessionCookieSetter<S> {
+    inner: S,
+    action: Option<SessionCookie>,
+}
+
+/// A plugin that sets or clears the session cookie according to the `@sessionCookie` trait
+/// of the operation, when session mode is enabled.
+#[derive(Debug, Clone)]
+pub struct SessionCookiePlugin {
+    model: Arc<SmithyModel>,
+}
+
+impl SessionCookiePlugin {
+    pub fn new(model: Arc<SmithyModel>) -> Self {
+        Self { model }
+    }
+}
+
+impl<Ser, Op, T> Plugin<Ser, Op, T> for SessionCookiePlugin
+where
+    Op: OperationShape,
+{
+    type Output = SessionCookieSetter<T>;
+
+    fn apply(&self, inner: T) -> Self::Output {
+        SessionCookieSetter {
+            inner,
+            action: self.model.session_cookie(Op::ID.absolute()),
+        }
+    }
+}
+
+impl HttpMarker for SessionCookiePlugin {}
+
+/// Access token an operation issues for the session cookie, handed by the handler to the plugin
+/// through the request extensions. Setting it does nothing outside session mode.
+#[derive(Debug, Clone, Default)]
+pub struct IssuedSession(Option<Arc<Mutex<Option<(String, u64)>>>>);
+
+impl IssuedSession {
+    /// Issue the session cookie for the token of the output, valid for `expires_in` seconds.
+    pub fn set(&self, token: &str, expires_in: u64) {
+        if let Some(slot) = &self.0 {
+            *slot.lock().unwrap() = Some((token.to_string(), expires_in));
+        }
+    }
+
+    fn take(&self) -> Option<(String, u64)> {
+        self.0.as_ref()?.lock().unwrap().take()
+    }
+}
+
+impl<P> FromParts<P> for IssuedSession {
+    type Rejection = Infallible;
+
+    fn from_parts(parts: &mut Parts) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<IssuedSession>()
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+impl<Body, S> Service<Request<Body>> for SessionCookieSetter<S>
+where
+    S: Service<Request<Body>, Response = Response<BoxBody>>,
+    S::Future: std::marker::Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Send + Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
+        let session = req
+            .extensions()
+            .get::<Arc<AppState>>()
+            .and_then(|s| s.config.auth.session.clone());
+        let (Some(action), Some(session)) = (self.action, session) else {
+            return Box::pin(self.inner.call(req));
+        };
+
+        let issued = IssuedSession(Some(Default::default()));
+        req.extensions_mut().insert(issued.clone());
+        let fut = self.inner.call(req);
+        Box::pin(async move {
+            let res = fut.await?;
+            if !res.status().is_success() {
+                return Ok(res);
+            }
+            Ok(match action {
+                SessionCookie::Issue => issue(res, &session, &issued),
+                SessionCookie::Clear => with_cookies(res, session.clear()),
+            })
+        })
+    }
+}
+
+/// Set the session cookies for the token issued by the handler.
+fn issue(
+    res: Response<BoxBody>,
+    session: &SessionConfig,
+    issued: &IssuedSession,
+) -> Response<BoxBody> {
+    match issued.take() {
+        Some((token, expires_in)) => with_cookies(res, session.issue(&token, expires_in)),
+        // e.g. the MFA challenge of a signin
+        None => {
+            debug!("no token issued for the session cookie");
+            res
+        }
+    }
+}
+
+fn with_cookies(mut res: Response<BoxBody>, cookies: [String; 2]) -> Response<BoxBody> {
+    for cookie in cookies {
+        match HeaderValue::try_from(cookie) {
+            Ok(value) => {
+                res.headers_mut().append(header::SET_COOKIE, value);
+            }
+            Err(e) => warn!("invalid session cookie: {}", e),
+        }
+    }
+    res
+}
@@ -0,0 +1,20 @@
+use aws_smithy_http_server::request::FromParts;
+use axum::http::{header, request::Parts};
+use std::convert::Infallible;
+
+/// `User-Agent` of the client, to tell the devices of a user apart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserAgent(pub Option<String>);
+
+impl<P> FromParts<P> for UserAgent {
+    type Rejection = Infallible;
+
+    fn from_parts(parts: &mut Parts) -> Result<Self, Self::Rejection> {
+        let user_agent = parts
+            .headers
+            .get(header::USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .map(ToString::to_string);
+        Ok(Self(user_agent))
+    }
+}
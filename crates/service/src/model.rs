@@ -0,0 +1,221 @@
+//! Smithy model metadata that the generated server SDK does not expose.
+//!
+//! `smithy build` emits the JSON AST of the model to `model/model.json`, we read
+//! traits like `@auth` from there so middleware can be driven by the model.
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+pub const HTTP_BEARER_AUTH: &str = "smithy.api#httpBearerAuth";
+pub const HTTP_API_KEY_AUTH: &str = "smithy.api#httpApiKeyAuth";
+
+/// Custom traits defined in `traits.smithy`.
+pub const REQUIRED_SCOPES_TRAIT: &str = "com.example#requiredScopes";
+pub const MTLS_AUTH: &str = "com.example#mtlsAuth";
+pub const SESSION_COOKIE_TRAIT: &str = "com.example#sessionCookie";
+
+const AUTH_TRAIT: &str = "smithy.api#auth";
+const AUTH_DEFINITION_TRAIT: &str = "smithy.api#authDefinition";
+
+/// Auth scheme traits defined in the Smithy prelude, they are not part of `model.json`.
+const PRELUDE_AUTH_SCHEMES: &[&str] = &[
+    HTTP_API_KEY_AUTH,
+    "smithy.api#httpBasicAuth",
+    HTTP_BEARER_AUTH,
+    "smithy.api#httpDigestAuth",
+];
+
+#[derive(Debug, Error)]
+pub enum ModelError {
+    #[error("invalid model json: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("model json has no shapes")]
+    MissingShapes,
+}
+
+/// Where a client puts its API key, the value of the `@httpApiKeyAuth` trait.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ApiKeyAuth {
+    /// name of the header or query parameter
+    pub name: String,
+    #[serde(rename = "in")]
+    pub location: ApiKeyLocation,
+    /// scheme prefixing the key in the header, e.g. `ApiKey` in `Authorization: ApiKey <key>`
+    #[serde(default)]
+    pub scheme: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiKeyLocation {
+    Header,
+    Query,
+}
+
+/// Value of the `@sessionCookie` trait.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionCookie {
+    Issue,
+    Clear,
+}
+
+#[derive(Debug, Clone)]
+pub struct SmithyModel {
+    shapes: Map<String, Value>,
+}
+
+impl SmithyModel {
+    pub fn try_new(json: &str) -> Result<Self, ModelError> {
+        let mut value: Value = serde_json::from_str(json)?;
+        let shapes = match value.get_mut("shapes").map(Value::take) {
+            Some(Value::Object(shapes)) => shapes,
+            _ => return Err(ModelError::MissingShapes),
+        };
+        Ok(Self { shapes })
+    }
+
+    /// Effective auth schemes of an operation in priority order, empty if the operation is anonymous.
+    ///
+    /// Follows the Smithy resolution rules: the operation `@auth` trait wins, then the service `@auth`
+    /// trait, then every auth scheme applied to the service sorted by shape id.
+    pub fn auth_schemes(&self, service: &str, operation: &str) -> Vec<String> {
+        if let Some(schemes) = self.traits(operation).and_then(|t| t.get(AUTH_TRAIT)) {
+            return to_strings(schemes);
+        }
+        self.service_auth_schemes(service)
+    }
+
+    /// Auth schemes of operations without an `@auth` trait.
+    pub fn service_auth_schemes(&self, service: &str) -> Vec<String> {
+        let Some(traits) = self.traits(service) else {
+            return vec![];
+        };
+        if let Some(schemes) = traits.get(AUTH_TRAIT) {
+            return to_strings(schemes);
+        }
+
+        let mut schemes: Vec<String> = traits
+            .keys()
+            .filter(|id| self.is_auth_scheme(id))
+            .cloned()
+            .collect();
+        schemes.sort();
+        schemes
+    }
+
+    /// Configuration of the `@httpApiKeyAuth` trait of the service.
+    pub fn api_key_auth(&self, service: &str) -> Option<ApiKeyAuth> {
+        let value = self.traits(service)?.get(HTTP_API_KEY_AUTH)?;
+        serde_json::from_value(value.clone()).ok()
+    }
+
+    /// Scopes required by the `@requiredScopes` trait of an operation, empty if it has none.
+    pub fn required_scopes(&self, operation: &str) -> Vec<String> {
+        self.traits(operation)
+            .and_then(|t| t.get(REQUIRED_SCOPES_TRAIT))
+            .map(to_strings)
+            .unwrap_or_default()
+    }
+
+    /// How the `@sessionCookie` trait of an operation changes the session cookie.
+    pub fn session_cookie(&self, operation: &str) -> Option<SessionCookie> {
+        let value = self.traits(operation)?.get(SESSION_COOKIE_TRAIT)?;
+        serde_json::from_value(value.clone()).ok()
+    }
+
+    fn traits(&self, id: &str) -> Option<&Map<String, Value>> {
+        self.shapes.get(id)?.get("traits")?.as_object()
+    }
+
+    fn is_auth_scheme(&self, id: &str) -> bool {
+        PRELUDE_AUTH_SCHEMES.contains(&id)
+            || self
+                .traits(id)
+                .map(|t| t.contains_key(AUTH_DEFINITION_TRAIT))
+                .unwrap_or(false)
+    }
+}
+
+fn to_strings(value: &Value) -> Vec<String> {
+    value
+        .as_array()
+        .map(|v| {
+            v.iter()
+                .filter_map(|s| s.as_str().map(ToString::to_string))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODEL: &str = r#"{
+        "smithy": "2.0",
+        "shapes": {
+            "com.example#EchoService": {
+                "type": "service",
+                "operations": [{ "target": "com.example#Echo" }, { "target": "com.example#Signin" }],
+                "traits": {
+                    "aws.protocols#restJson1": {},
+                    "smithy.api#httpApiKeyAuth": { "name": "X-Api-Key", "in": "header" },
+                    "smithy.api#httpBearerAuth": {}
+                }
+            },
+            "com.example#Echo": {
+                "type": "operation",
+                "traits": { "com.example#requiredScopes": ["echo:write"] }
+            },
+            "com.example#Signin": {
+                "type": "operation",
+                "traits": { "smithy.api#auth": [], "com.example#sessionCookie": "issue" }
+            }
+        }
+    }"#;
+
+    #[test]
+    fn operation_should_inherit_service_auth_schemes() {
+        let model = SmithyModel::try_new(MODEL).unwrap();
+        let schemes = model.auth_schemes("com.example#EchoService", "com.example#Echo");
+        assert_eq!(schemes, vec![HTTP_API_KEY_AUTH, HTTP_BEARER_AUTH]);
+    }
+
+    #[test]
+    fn api_key_auth_should_be_read_from_service() {
+        let model = SmithyModel::try_new(MODEL).unwrap();
+        let auth = model.api_key_auth("com.example#EchoService").unwrap();
+        assert_eq!(auth.name, "X-Api-Key");
+        assert_eq!(auth.location, ApiKeyLocation::Header);
+        assert_eq!(auth.scheme, None);
+    }
+
+    #[test]
+    fn operation_auth_trait_should_override_service() {
+        let model = SmithyModel::try_new(MODEL).unwrap();
+        let schemes = model.auth_schemes("com.example#EchoService", "com.example#Signin");
+        assert!(schemes.is_empty());
+    }
+
+    #[test]
+    fn required_scopes_should_be_read_from_operation() {
+        let model = SmithyModel::try_new(MODEL).unwrap();
+        assert_eq!(
+            model.required_scopes("com.example#Echo"),
+            vec!["echo:write"]
+        );
+        assert!(model.required_scopes("com.example#Signin").is_empty());
+    }
+
+    #[test]
+    fn session_cookie_should_be_read_from_operation() {
+        let model = SmithyModel::try_new(MODEL).unwrap();
+        assert_eq!(
+            model.session_cookie("com.example#Signin"),
+            Some(SessionCookie::Issue)
+        );
+        assert_eq!(model.session_cookie("com.example#Echo"), None);
+    }
+}
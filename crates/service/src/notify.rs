@@ -0,0 +1,185 @@
+//! Delivery of messages to users, e.g. password reset tokens.
+//!
+//! Users have no contact details yet, so the bundled notifiers only log or record the
+//! messages for local testing. Real delivery plugs in by implementing [`Notifier`].
+
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+use thiserror::Error;
+use tracing::info;
+
+#[derive(Debug, Error)]
+pub enum NotifyError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("serde error: {0}")]
+    Serde(#[from] serde_json::Error),
+}
+
+/// A message to a user.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Notification {
+    PasswordReset {
+        username: String,
+        token: String,
+        expires_in: u64,
+    },
+    PasswordChanged {
+        username: String,
+    },
+}
+
+pub trait Notifier: std::fmt::Debug + Send + Sync {
+    fn notify(&self, notification: &Notification) -> Result<(), NotifyError>;
+}
+
+/// Logs notifications, secrets are redacted unless `reveal_secrets` is set.
+#[derive(Debug, Default)]
+pub struct LogNotifier {
+    reveal_secrets: bool,
+}
+
+/// Appends notifications as json lines to a file.
+#[derive(Debug)]
+pub struct FileNotifier {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NotifierConfig {
+    Log {
+        /// log password reset tokens as is, only for local development
+        #[serde(default)]
+        reveal_secrets: bool,
+    },
+    File {
+        path: PathBuf,
+    },
+}
+
+impl NotifierConfig {
+    pub fn notifier(&self) -> Arc<dyn Notifier> {
+        match self {
+            Self::Log { reveal_secrets } => Arc::new(LogNotifier {
+                reveal_secrets: *reveal_secrets,
+            }),
+            Self::File { path } => Arc::new(FileNotifier::new(path.clone())),
+        }
+    }
+}
+
+impl Default for NotifierConfig {
+    fn default() -> Self {
+        Self::Log {
+            reveal_secrets: false,
+        }
+    }
+}
+
+impl Notification {
+    /// The notification without its secrets, safe to log.
+    pub fn redacted(&self) -> Self {
+        match self {
+            Self::PasswordReset {
+                username,
+                expires_in,
+                ..
+            } => Self::PasswordReset {
+                username: username.clone(),
+                token: "<redacted>".to_string(),
+                expires_in: *expires_in,
+            },
+            Self::PasswordChanged { .. } => self.clone(),
+        }
+    }
+}
+
+impl Notifier for LogNotifier {
+    fn notify(&self, notification: &Notification) -> Result<(), NotifyError> {
+        let json = if self.reveal_secrets {
+            serde_json::to_string(notification)?
+        } else {
+            serde_json::to_string(&notification.redacted())?
+        };
+        info!("notification: {}", json);
+        Ok(())
+    }
+}
+
+impl FileNotifier {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+}
+
+impl Notifier for FileNotifier {
+    fn notify(&self, notification: &Notification) -> Result<(), NotifyError> {
+        let mut line = serde_json::to_string(notification)?;
+        line.push('\n');
+        let _guard = self.lock.lock().unwrap();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_notifier_should_append_json_lines() {
+        let path = std::env::temp_dir().join(format!("notifications-{}.jsonl", uuid7::uuid7()));
+        let notifier = FileNotifier::new(path.clone());
+        for username in ["alice", "bob"] {
+            let notification = Notification::PasswordChanged {
+                username: username.to_string(),
+            };
+            notifier.notify(&notification).unwrap();
+        }
+
+        let lines = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = lines
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["kind"], "password_changed");
+        assert_eq!(lines[1]["username"], "bob");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn redacted_notification_should_hide_the_token() {
+        let notification = Notification::PasswordReset {
+            username: "alice".to_string(),
+            token: "secret-token".to_string(),
+            expires_in: 1800,
+        };
+        let json = serde_json::to_string(&notification.redacted()).unwrap();
+        assert!(!json.contains("secret-token"));
+        assert!(json.contains("alice"));
+
+        let config: NotifierConfig = serde_json::from_str(r#"{"type":"log"}"#).unwrap();
+        assert!(matches!(
+            config,
+            NotifierConfig::Log {
+                reveal_secrets: false
+            }
+        ));
+    }
+}
@@ -0,0 +1,245 @@
+//! OAuth2 token endpoint (RFC 6749) for machine identities.
+//!
+//! Only the `client_credentials` grant is supported. Token requests are form encoded, which the
+//! restJson1 protocol can't model, so the endpoint lives beside the Smithy service.
+
+use crate::{
+    audit::{AuditContext, AuditEvent},
+    AppState, REQUEST_ID_HEADER,
+};
+use aws_smithy_http_server::request::request_id::ServerRequestId;
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Form, Json,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use std::{fmt, sync::Arc};
+use tracing::{info, warn};
+
+pub const TOKEN_PATH: &str = "/oauth2/token";
+
+const CLIENT_CREDENTIALS: &str = "client_credentials";
+
+#[derive(Debug, Deserialize)]
+pub struct TokenRequest {
+    grant_type: String,
+    /// space separated scopes
+    #[serde(default)]
+    scope: Option<String>,
+    /// client credentials in the body, when not given by the `Authorization` header
+    #[serde(default)]
+    client_id: Option<String>,
+    #[serde(default)]
+    client_secret: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenResponse {
+    access_token: String,
+    token_type: &'static str,
+    expires_in: u64,
+    scope: String,
+}
+
+/// Error response of RFC 6749 section 5.2.
+#[derive(Debug)]
+pub struct OAuthError {
+    code: &'static str,
+    description: String,
+}
+
+pub async fn token(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    audit: AuditContext,
+    Form(req): Form<TokenRequest>,
+) -> Result<impl IntoResponse, OAuthError> {
+    let mut principal = None;
+    let result = issue(&state, &headers, req, &mut principal);
+    state.audit(audit.record(AuditEvent::ClientToken, principal.as_deref(), &result));
+    let response = result?;
+    Ok(([(header::CACHE_CONTROL, "no-store")], Json(response)))
+}
+
+/// Give token requests an id like the requests of the api get, for their audit entries.
+pub async fn with_request_id<B>(mut req: Request<B>, next: Next<B>) -> Response {
+    let id = ServerRequestId::new();
+    let header = HeaderValue::from_str(&id.to_string()).ok();
+    req.extensions_mut().insert(id);
+    let mut res = next.run(req).await;
+    if let Some(header) = header {
+        res.headers_mut().insert(REQUEST_ID_HEADER, header);
+    }
+    res
+}
+
+/// Issue a token to an authenticated client, `principal` is set once the client id is known.
+fn issue(
+    state: &AppState,
+    headers: &HeaderMap,
+    req: TokenRequest,
+    principal: &mut Option<String>,
+) -> Result<TokenResponse, OAuthError> {
+    if req.grant_type != CLIENT_CREDENTIALS {
+        return Err(OAuthError::new(
+            "unsupported_grant_type",
+            format!("grant type {} is not supported", req.grant_type),
+        ));
+    }
+    let Some((id, secret)) = credentials(headers, &req) else {
+        return Err(OAuthError::new(
+            "invalid_client",
+            "client authentication is required",
+        ));
+    };
+    *principal = Some(format!("client:{}", id));
+    let client = state.config.auth.client(&id, &secret).map_err(|e| {
+        warn!("client {} rejected: {}", id, e);
+        OAuthError::new("invalid_client", e)
+    })?;
+    let scopes = client
+        .grant(req.scope.as_deref())
+        .map_err(|e| OAuthError::new("invalid_scope", e))?;
+
+    info!("client credentials token: {} {:?}", client.id, scopes);
+    let signer = &state.signer;
+    let lifetime = signer.lifetime(None);
+    let access_token = signer
+        .sign(client.claims(scopes.clone()), lifetime)
+        .map_err(|e| OAuthError::new("server_error", e))?;
+    Ok(TokenResponse {
+        access_token,
+        token_type: "Bearer",
+        expires_in: lifetime,
+        scope: scopes.join(" "),
+    })
+}
+
+/// Client id and secret of HTTP basic auth, or of the body.
+fn credentials(headers: &HeaderMap, req: &TokenRequest) -> Option<(String, String)> {
+    let basic = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Basic "));
+    if let Some(basic) = basic {
+        let decoded = STANDARD.decode(basic.trim()).ok()?;
+        let decoded = String::from_utf8(decoded).ok()?;
+        let (id, secret) = decoded.split_once(':')?;
+        return Some((form_decode(id)?, form_decode(secret)?));
+    }
+    Some((req.client_id.clone()?, req.client_secret.clone()?))
+}
+
+/// Basic credentials are form encoded before base64 (RFC 6749 section 2.3.1).
+fn form_decode(value: &str) -> Option<String> {
+    let value = value.replace('+', " ");
+    let decoded = percent_decode_str(&value).decode_utf8().ok()?;
+    Some(decoded.into_owned())
+}
+
+impl OAuthError {
+    fn new(code: &'static str, description: impl ToString) -> Self {
+        Self {
+            code,
+            description: description.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for OAuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.description)
+    }
+}
+
+impl IntoResponse for OAuthError {
+    fn into_response(self) -> Response {
+        let status = match self.code {
+            "invalid_client" => StatusCode::UNAUTHORIZED,
+            "server_error" => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        };
+        let body = serde_json::json!({
+            "error": self.code,
+            "error_description": self.description,
+        });
+        let mut res = (status, [(header::CACHE_CONTROL, "no-store")], Json(body)).into_response();
+        if status == StatusCode::UNAUTHORIZED {
+            let challenge = header::HeaderValue::from_static("Basic");
+            res.headers_mut()
+                .insert(header::WWW_AUTHENTICATE, challenge);
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AppConfig, AuditConfig, FileAuditSink};
+    use axum::{body::Body, middleware::from_fn, routing::post, Router};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn token_request_should_be_audited_with_its_request_id() {
+        let path = std::env::temp_dir().join(format!("audit-{}.jsonl", uuid7::uuid7()));
+        let config = AppConfig {
+            audit: Some(AuditConfig::File {
+                path: path.clone(),
+                key: Some("audit-key".to_string()),
+                key_env: None,
+            }),
+            ..Default::default()
+        };
+        let state = Arc::new(AppState::try_new(config).unwrap());
+        let app = Router::new()
+            .route(TOKEN_PATH, post(token).layer(from_fn(with_request_id)))
+            .with_state(state);
+        let req = Request::post(TOKEN_PATH)
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(
+                "grant_type=client_credentials&client_id=billing&client_secret=wrong",
+            ))
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let request_id = res.headers()[REQUEST_ID_HEADER].to_str().unwrap();
+
+        let entries = FileAuditSink::try_new(path.clone(), b"audit-key")
+            .unwrap()
+            .entries()
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].record.event, AuditEvent::ClientToken);
+        assert_eq!(
+            entries[0].record.principal.as_deref(),
+            Some("client:billing")
+        );
+        assert_eq!(entries[0].record.request_id.as_deref(), Some(request_id));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn basic_credentials_should_be_form_decoded() {
+        let basic = STANDARD.encode("billing%3Aeu:s3cret+%25");
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            format!("Basic {}", basic).parse().unwrap(),
+        );
+        let req = TokenRequest {
+            grant_type: CLIENT_CREDENTIALS.to_string(),
+            scope: None,
+            client_id: None,
+            client_secret: None,
+        };
+        let (id, secret) = credentials(&headers, &req).unwrap();
+        assert_eq!(id, "billing:eu");
+        assert_eq!(secret, "s3cret %");
+    }
+}
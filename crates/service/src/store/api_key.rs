@@ -0,0 +1,137 @@
+use super::{load, Result, StoreError, StoreFile};
+use derive_more::Debug;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::Path, sync::RwLock};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub id: String,
+    /// sha256 of the secret part of the key, the key itself is never stored
+    #[debug(skip)]
+    pub hash: String,
+    /// username of the user the key acts for
+    pub owner: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+    /// unix timestamps in seconds
+    pub created_at: u64,
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+    #[serde(default)]
+    pub last_used_at: Option<u64>,
+}
+
+/// Storage of API keys.
+pub trait ApiKeyStore: std::fmt::Debug + Send + Sync {
+    fn get(&self, id: &str) -> Result<Option<ApiKey>>;
+    fn insert(&self, key: ApiKey) -> Result<()>;
+    /// Keys of an owner ordered by creation.
+    fn list(&self, owner: &str) -> Result<Vec<ApiKey>>;
+    /// Remove a key, fails with [`StoreError::NotFound`] if the id is unknown.
+    fn remove(&self, id: &str) -> Result<()>;
+    /// Record the last time a key was used.
+    fn touch(&self, id: &str, at: u64) -> Result<()>;
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryApiKeyStore {
+    keys: RwLock<HashMap<String, ApiKey>>,
+}
+
+/// An API key store persisted as a json file, all keys are kept in memory.
+///
+/// Last used timestamps are only kept in memory, persisting them on every request is not worth it.
+#[derive(Debug)]
+pub struct FileApiKeyStore {
+    file: StoreFile,
+    inner: MemoryApiKeyStore,
+}
+
+impl MemoryApiKeyStore {
+    fn snapshot(&self) -> Vec<ApiKey> {
+        let keys = self.keys.read().unwrap();
+        let mut keys: Vec<_> = keys.values().cloned().collect();
+        keys.sort_by(|a, b| a.id.cmp(&b.id));
+        keys
+    }
+}
+
+impl ApiKeyStore for MemoryApiKeyStore {
+    fn get(&self, id: &str) -> Result<Option<ApiKey>> {
+        Ok(self.keys.read().unwrap().get(id).cloned())
+    }
+
+    fn insert(&self, key: ApiKey) -> Result<()> {
+        let mut keys = self.keys.write().unwrap();
+        if keys.contains_key(&key.id) {
+            return Err(StoreError::AlreadyExists(format!("api key {}", key.id)));
+        }
+        keys.insert(key.id.clone(), key);
+        Ok(())
+    }
+
+    fn list(&self, owner: &str) -> Result<Vec<ApiKey>> {
+        let keys = self.keys.read().unwrap();
+        let mut keys: Vec<_> = keys
+            .values()
+            .filter(|k| k.owner == owner)
+            .cloned()
+            .collect();
+        keys.sort_by_key(|k| k.created_at);
+        Ok(keys)
+    }
+
+    fn remove(&self, id: &str) -> Result<()> {
+        match self.keys.write().unwrap().remove(id) {
+            Some(_) => Ok(()),
+            None => Err(StoreError::NotFound(format!("api key {}", id))),
+        }
+    }
+
+    fn touch(&self, id: &str, at: u64) -> Result<()> {
+        if let Some(key) = self.keys.write().unwrap().get_mut(id) {
+            key.last_used_at = Some(at);
+        }
+        Ok(())
+    }
+}
+
+impl FileApiKeyStore {
+    pub fn try_new(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let keys: Vec<ApiKey> = load(&path)?;
+        let inner = MemoryApiKeyStore {
+            keys: RwLock::new(keys.into_iter().map(|k| (k.id.clone(), k)).collect()),
+        };
+        Ok(Self {
+            file: StoreFile::new(path),
+            inner,
+        })
+    }
+}
+
+impl ApiKeyStore for FileApiKeyStore {
+    fn get(&self, id: &str) -> Result<Option<ApiKey>> {
+        self.inner.get(id)
+    }
+
+    fn insert(&self, key: ApiKey) -> Result<()> {
+        let inner = &self.inner;
+        self.file
+            .commit(&inner.keys, || inner.insert(key), || inner.snapshot())
+    }
+
+    fn list(&self, owner: &str) -> Result<Vec<ApiKey>> {
+        self.inner.list(owner)
+    }
+
+    fn remove(&self, id: &str) -> Result<()> {
+        let inner = &self.inner;
+        self.file
+            .commit(&inner.keys, || inner.remove(id), || inner.snapshot())
+    }
+
+    fn touch(&self, id: &str, at: u64) -> Result<()> {
+        self.inner.touch(id, at)
+    }
+}
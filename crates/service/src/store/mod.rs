@@ -0,0 +1,156 @@
+mod api_key;
+mod refresh_token;
+mod revocation;
+mod session;
+mod user;
+
+pub use api_key::{ApiKey, ApiKeyStore, FileApiKeyStore, MemoryApiKeyStore};
+pub use refresh_token::{
+    FileRefreshTokenStore, MemoryRefreshTokenStore, RefreshToken, RefreshTokenStore,
+};
+pub use revocation::{FileRevocationStore, MemoryRevocationStore, RevocationStore};
+pub use session::{FileSessionStore, MemorySessionStore, Session, SessionStore};
+pub use user::{FileUserStore, MemoryUserStore, User, UserMfa, UserStore};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum StoreError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("serde error: {0}")]
+    Serde(#[from] serde_json::Error),
+    #[error("{0} already exists")]
+    AlreadyExists(String),
+    #[error("{0} not found")]
+    NotFound(String),
+}
+
+type Result<T> = std::result::Result<T, StoreError>;
+
+/// Where the stores keep their data. Stores without a path are kept in memory.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StoreConfig {
+    #[serde(default)]
+    pub users: Option<PathBuf>,
+    #[serde(default)]
+    pub revocations: Option<PathBuf>,
+    #[serde(default)]
+    pub api_keys: Option<PathBuf>,
+    #[serde(default)]
+    pub sessions: Option<PathBuf>,
+    #[serde(default)]
+    pub refresh_tokens: Option<PathBuf>,
+}
+
+impl StoreConfig {
+    pub fn user_store(&self) -> Result<Arc<dyn UserStore>> {
+        Ok(match &self.users {
+            Some(path) => Arc::new(FileUserStore::try_new(path)?),
+            None => Arc::new(MemoryUserStore::default()),
+        })
+    }
+
+    pub fn revocation_store(&self) -> Result<Arc<dyn RevocationStore>> {
+        Ok(match &self.revocations {
+            Some(path) => Arc::new(FileRevocationStore::try_new(path)?),
+            None => Arc::new(MemoryRevocationStore::default()),
+        })
+    }
+
+    pub fn api_key_store(&self) -> Result<Arc<dyn ApiKeyStore>> {
+        Ok(match &self.api_keys {
+            Some(path) => Arc::new(FileApiKeyStore::try_new(path)?),
+            None => Arc::new(MemoryApiKeyStore::default()),
+        })
+    }
+
+    pub fn session_store(&self) -> Result<Arc<dyn SessionStore>> {
+        Ok(match &self.sessions {
+            Some(path) => Arc::new(FileSessionStore::try_new(path)?),
+            None => Arc::new(MemorySessionStore::default()),
+        })
+    }
+
+    pub fn refresh_token_store(&self) -> Result<Arc<dyn RefreshTokenStore>> {
+        Ok(match &self.refresh_tokens {
+            Some(path) => Arc::new(FileRefreshTokenStore::try_new(path)?),
+            None => Arc::new(MemoryRefreshTokenStore::default()),
+        })
+    }
+}
+
+/// Current unix timestamp in seconds.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Load a json file, a missing file is treated as empty data.
+fn load<T: DeserializeOwned + Default>(path: &Path) -> Result<T> {
+    match fs::read(path) {
+        Ok(data) => Ok(serde_json::from_slice(&data)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Write data to a temporary file next to `path` then rename it, so a crash never leaves a partial file.
+fn persist<T: Serialize + ?Sized>(path: &Path, data: &T) -> Result<()> {
+    // unique, a file left by a crashed write is never renamed over a newer one
+    let tmp = path.with_extension(format!("{}.tmp", uuid7::uuid7()));
+    fs::write(&tmp, serde_json::to_vec_pretty(data)?)?;
+    if let Err(e) = fs::rename(&tmp, path) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// The json file of a file store, whose data is kept in memory.
+#[derive(Debug)]
+struct StoreFile {
+    path: PathBuf,
+    /// held from a change of the data until it is written, so writes land in order
+    lock: Mutex<()>,
+}
+
+impl StoreFile {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Apply `change` to the data in memory then write the `snapshot` of the data.
+    ///
+    /// The data is restored if the change or the write fails, so memory and file don't diverge.
+    /// Changes that are not persisted, like last used timestamps, may be undone with it.
+    fn commit<D: Clone, S: Serialize, T>(
+        &self,
+        data: &RwLock<D>,
+        change: impl FnOnce() -> Result<T>,
+        snapshot: impl FnOnce() -> S,
+    ) -> Result<T> {
+        let _guard = self.lock.lock().unwrap();
+        let backup = data.read().unwrap().clone();
+        let result = change().and_then(|value| {
+            persist(&self.path, &snapshot())?;
+            Ok(value)
+        });
+        if result.is_err() {
+            *data.write().unwrap() = backup;
+        }
+        result
+    }
+}
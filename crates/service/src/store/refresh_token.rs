@@ -0,0 +1,149 @@
+use super::{load, now, Result, StoreFile};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::Path, sync::RwLock};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RefreshToken {
+    /// sha256 of the token, the token itself is never stored
+    pub hash: String,
+    /// all tokens rotated from the same signin share a family
+    pub family: String,
+    pub username: String,
+    /// unix timestamp in seconds
+    pub expires_at: u64,
+    #[serde(default)]
+    pub used: bool,
+}
+
+/// Storage of refresh tokens. Used tokens are kept until they expire so that reuse can be detected.
+pub trait RefreshTokenStore: std::fmt::Debug + Send + Sync {
+    fn get(&self, hash: &str) -> Result<Option<RefreshToken>>;
+    fn insert(&self, token: RefreshToken) -> Result<()>;
+    /// Mark a token as used and return it as it was before, `None` if the token is unknown.
+    fn mark_used(&self, hash: &str) -> Result<Option<RefreshToken>>;
+    /// Remove all tokens of a family.
+    fn revoke_family(&self, family: &str) -> Result<()>;
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryRefreshTokenStore {
+    tokens: RwLock<HashMap<String, RefreshToken>>,
+}
+
+/// A refresh token store persisted as a json file, so tokens outlive restarts like their sessions.
+#[derive(Debug)]
+pub struct FileRefreshTokenStore {
+    file: StoreFile,
+    inner: MemoryRefreshTokenStore,
+}
+
+impl MemoryRefreshTokenStore {
+    fn snapshot(&self) -> Vec<RefreshToken> {
+        let tokens = self.tokens.read().unwrap();
+        let mut tokens: Vec<_> = tokens.values().cloned().collect();
+        tokens.sort_by(|a, b| a.hash.cmp(&b.hash));
+        tokens
+    }
+}
+
+impl RefreshTokenStore for MemoryRefreshTokenStore {
+    fn get(&self, hash: &str) -> Result<Option<RefreshToken>> {
+        Ok(self.tokens.read().unwrap().get(hash).cloned())
+    }
+
+    fn insert(&self, token: RefreshToken) -> Result<()> {
+        let now = now();
+        let mut tokens = self.tokens.write().unwrap();
+        tokens.retain(|_, t| t.expires_at > now);
+        tokens.insert(token.hash.clone(), token);
+        Ok(())
+    }
+
+    fn mark_used(&self, hash: &str) -> Result<Option<RefreshToken>> {
+        let mut tokens = self.tokens.write().unwrap();
+        let Some(token) = tokens.get_mut(hash) else {
+            return Ok(None);
+        };
+        let old = token.clone();
+        token.used = true;
+        Ok(Some(old))
+    }
+
+    fn revoke_family(&self, family: &str) -> Result<()> {
+        self.tokens
+            .write()
+            .unwrap()
+            .retain(|_, t| t.family != family);
+        Ok(())
+    }
+}
+
+impl FileRefreshTokenStore {
+    pub fn try_new(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let tokens: Vec<RefreshToken> = load(&path)?;
+        let inner = MemoryRefreshTokenStore {
+            tokens: RwLock::new(tokens.into_iter().map(|t| (t.hash.clone(), t)).collect()),
+        };
+        Ok(Self {
+            file: StoreFile::new(path),
+            inner,
+        })
+    }
+}
+
+impl RefreshTokenStore for FileRefreshTokenStore {
+    fn get(&self, hash: &str) -> Result<Option<RefreshToken>> {
+        self.inner.get(hash)
+    }
+
+    fn insert(&self, token: RefreshToken) -> Result<()> {
+        let inner = &self.inner;
+        self.file
+            .commit(&inner.tokens, || inner.insert(token), || inner.snapshot())
+    }
+
+    fn mark_used(&self, hash: &str) -> Result<Option<RefreshToken>> {
+        let inner = &self.inner;
+        self.file
+            .commit(&inner.tokens, || inner.mark_used(hash), || inner.snapshot())
+    }
+
+    fn revoke_family(&self, family: &str) -> Result<()> {
+        let inner = &self.inner;
+        self.file.commit(
+            &inner.tokens,
+            || inner.revoke_family(family),
+            || inner.snapshot(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_store_should_persist_used_tokens() {
+        let path = std::env::temp_dir().join(format!("refresh-tokens-{}.json", uuid7::uuid7()));
+        let store = FileRefreshTokenStore::try_new(&path).unwrap();
+        store
+            .insert(RefreshToken {
+                hash: "hash".to_string(),
+                family: "session".to_string(),
+                username: "alice".to_string(),
+                expires_at: now() + 60,
+                used: false,
+            })
+            .unwrap();
+        store.mark_used("hash").unwrap();
+
+        // reuse is still detected after a restart
+        let store = FileRefreshTokenStore::try_new(&path).unwrap();
+        assert!(store.get("hash").unwrap().unwrap().used);
+        store.revoke_family("session").unwrap();
+        let store = FileRefreshTokenStore::try_new(&path).unwrap();
+        assert!(store.get("hash").unwrap().is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+}
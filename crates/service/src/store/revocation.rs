@@ -0,0 +1,91 @@
+use super::{load, now, Result, StoreFile};
+use std::{collections::HashMap, path::Path, sync::RwLock};
+
+/// Storage of revoked token ids. An entry is only needed until the token expires,
+/// after that the token is rejected anyway and the entry is evicted.
+pub trait RevocationStore: std::fmt::Debug + Send + Sync {
+    /// Revoke a token id until `expires_at` (unix timestamp in seconds).
+    fn revoke(&self, jti: &str, expires_at: u64) -> Result<()>;
+    fn is_revoked(&self, jti: &str) -> Result<bool>;
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryRevocationStore {
+    revoked: RwLock<HashMap<String, u64>>,
+}
+
+/// A revocation store persisted as a json file, so revoked tokens stay revoked across restarts.
+#[derive(Debug)]
+pub struct FileRevocationStore {
+    file: StoreFile,
+    inner: MemoryRevocationStore,
+}
+
+impl MemoryRevocationStore {
+    /// Evict expired entries and return the remaining ones.
+    fn evict(&self) -> HashMap<String, u64> {
+        let now = now();
+        let mut revoked = self.revoked.write().unwrap();
+        revoked.retain(|_, expires_at| *expires_at > now);
+        revoked.clone()
+    }
+}
+
+impl RevocationStore for MemoryRevocationStore {
+    fn revoke(&self, jti: &str, expires_at: u64) -> Result<()> {
+        self.evict();
+        self.revoked
+            .write()
+            .unwrap()
+            .insert(jti.to_string(), expires_at);
+        Ok(())
+    }
+
+    fn is_revoked(&self, jti: &str) -> Result<bool> {
+        let revoked = self.revoked.read().unwrap();
+        Ok(matches!(revoked.get(jti), Some(expires_at) if *expires_at > now()))
+    }
+}
+
+impl FileRevocationStore {
+    pub fn try_new(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let inner = MemoryRevocationStore {
+            revoked: RwLock::new(load(&path)?),
+        };
+        Ok(Self {
+            file: StoreFile::new(path),
+            inner,
+        })
+    }
+}
+
+impl RevocationStore for FileRevocationStore {
+    fn revoke(&self, jti: &str, expires_at: u64) -> Result<()> {
+        let inner = &self.inner;
+        self.file.commit(
+            &inner.revoked,
+            || inner.revoke(jti, expires_at),
+            || inner.evict(),
+        )
+    }
+
+    fn is_revoked(&self, jti: &str) -> Result<bool> {
+        self.inner.is_revoked(jti)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expired_entries_should_be_evicted() {
+        let store = MemoryRevocationStore::default();
+        store.revoke("old", now() - 1).unwrap();
+        store.revoke("new", now() + 60).unwrap();
+        assert!(!store.is_revoked("old").unwrap());
+        assert!(store.is_revoked("new").unwrap());
+        assert_eq!(store.evict().len(), 1);
+    }
+}
@@ -0,0 +1,165 @@
+use super::{load, now, Result, StoreError, StoreFile};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::Path, sync::RwLock};
+
+/// A signin of a user on a device, it lasts as long as its refresh tokens.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    /// also the family of the refresh tokens of the session
+    pub id: String,
+    pub username: String,
+    /// `User-Agent` of the signin
+    #[serde(default)]
+    pub user_agent: Option<String>,
+    /// client ip of the signin
+    #[serde(default)]
+    pub ip: Option<String>,
+    /// unix timestamps in seconds
+    pub created_at: u64,
+    pub last_seen_at: u64,
+    pub expires_at: u64,
+}
+
+/// Storage of sessions.
+pub trait SessionStore: std::fmt::Debug + Send + Sync {
+    fn get(&self, id: &str) -> Result<Option<Session>>;
+    fn insert(&self, session: Session) -> Result<()>;
+    /// Replace a session, fails with [`StoreError::NotFound`] if the id is unknown.
+    fn update(&self, session: Session) -> Result<()>;
+    /// Unexpired sessions of a user ordered by creation.
+    fn list(&self, username: &str) -> Result<Vec<Session>>;
+    /// Remove a session, fails with [`StoreError::NotFound`] if the id is unknown.
+    fn remove(&self, id: &str) -> Result<()>;
+    /// Record the last time a session was used.
+    fn touch(&self, id: &str, at: u64) -> Result<()>;
+}
+
+#[derive(Debug, Default)]
+pub struct MemorySessionStore {
+    sessions: RwLock<HashMap<String, Session>>,
+}
+
+/// A session store persisted as a json file, all sessions are kept in memory.
+///
+/// Like API keys, last seen timestamps are only kept in memory.
+#[derive(Debug)]
+pub struct FileSessionStore {
+    file: StoreFile,
+    inner: MemorySessionStore,
+}
+
+impl MemorySessionStore {
+    fn snapshot(&self) -> Vec<Session> {
+        let sessions = self.sessions.read().unwrap();
+        let mut sessions: Vec<_> = sessions.values().cloned().collect();
+        sessions.sort_by(|a, b| a.id.cmp(&b.id));
+        sessions
+    }
+}
+
+impl SessionStore for MemorySessionStore {
+    fn get(&self, id: &str) -> Result<Option<Session>> {
+        let sessions = self.sessions.read().unwrap();
+        Ok(sessions.get(id).filter(|s| s.expires_at > now()).cloned())
+    }
+
+    fn insert(&self, session: Session) -> Result<()> {
+        let now = now();
+        let mut sessions = self.sessions.write().unwrap();
+        sessions.retain(|_, s| s.expires_at > now);
+        if sessions.contains_key(&session.id) {
+            return Err(StoreError::AlreadyExists(format!("session {}", session.id)));
+        }
+        sessions.insert(session.id.clone(), session);
+        Ok(())
+    }
+
+    fn update(&self, session: Session) -> Result<()> {
+        let mut sessions = self.sessions.write().unwrap();
+        match sessions.get_mut(&session.id) {
+            Some(s) => {
+                *s = session;
+                Ok(())
+            }
+            None => Err(StoreError::NotFound(format!("session {}", session.id))),
+        }
+    }
+
+    fn list(&self, username: &str) -> Result<Vec<Session>> {
+        let now = now();
+        let sessions = self.sessions.read().unwrap();
+        let mut sessions: Vec<_> = sessions
+            .values()
+            .filter(|s| s.username == username && s.expires_at > now)
+            .cloned()
+            .collect();
+        sessions.sort_by_key(|s| s.created_at);
+        Ok(sessions)
+    }
+
+    fn remove(&self, id: &str) -> Result<()> {
+        match self.sessions.write().unwrap().remove(id) {
+            Some(_) => Ok(()),
+            None => Err(StoreError::NotFound(format!("session {}", id))),
+        }
+    }
+
+    fn touch(&self, id: &str, at: u64) -> Result<()> {
+        if let Some(session) = self.sessions.write().unwrap().get_mut(id) {
+            session.last_seen_at = at;
+        }
+        Ok(())
+    }
+}
+
+impl FileSessionStore {
+    pub fn try_new(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let sessions: Vec<Session> = load(&path)?;
+        let inner = MemorySessionStore {
+            sessions: RwLock::new(sessions.into_iter().map(|s| (s.id.clone(), s)).collect()),
+        };
+        Ok(Self {
+            file: StoreFile::new(path),
+            inner,
+        })
+    }
+}
+
+impl SessionStore for FileSessionStore {
+    fn get(&self, id: &str) -> Result<Option<Session>> {
+        self.inner.get(id)
+    }
+
+    fn insert(&self, session: Session) -> Result<()> {
+        let inner = &self.inner;
+        self.file.commit(
+            &inner.sessions,
+            || inner.insert(session),
+            || inner.snapshot(),
+        )
+    }
+
+    fn update(&self, session: Session) -> Result<()> {
+        let inner = &self.inner;
+        self.file.commit(
+            &inner.sessions,
+            || inner.update(session),
+            || inner.snapshot(),
+        )
+    }
+
+    fn list(&self, username: &str) -> Result<Vec<Session>> {
+        self.inner.list(username)
+    }
+
+    fn remove(&self, id: &str) -> Result<()> {
+        let inner = &self.inner;
+        self.file
+            .commit(&inner.sessions, || inner.remove(id), || inner.snapshot())
+    }
+
+    fn touch(&self, id: &str, at: u64) -> Result<()> {
+        self.inner.touch(id, at)
+    }
+}
@@ -0,0 +1,175 @@
+use super::{load, Result, StoreError, StoreFile};
+use derive_more::Debug;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, path::Path, sync::RwLock};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    pub username: String,
+    /// argon2 PHC string of the password
+    #[debug(skip)]
+    pub password_hash: String,
+    /// roles granting scopes to the tokens of the user
+    #[serde(default)]
+    pub roles: Vec<String>,
+    #[serde(default)]
+    pub disabled: bool,
+    /// unix timestamp in seconds
+    pub created_at: u64,
+    /// TOTP authenticator, required at signin once enabled
+    #[serde(default)]
+    pub mfa: Option<UserMfa>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserMfa {
+    /// base32 TOTP secret
+    #[debug(skip)]
+    pub secret: String,
+    /// set once a code of the authenticator is confirmed
+    pub enabled: bool,
+    /// sha256 of the unused recovery codes
+    #[debug(skip)]
+    pub recovery_codes: Vec<String>,
+    /// TOTP time step of the last accepted code, codes can't be replayed
+    pub last_step: u64,
+}
+
+/// Storage of user identities. Implementations must be cheap to call from async handlers.
+pub trait UserStore: std::fmt::Debug + Send + Sync {
+    fn get(&self, username: &str) -> Result<Option<User>>;
+    /// Insert a new user, fails with [`StoreError::AlreadyExists`] if the username is taken.
+    fn insert(&self, user: User) -> Result<()>;
+    /// Replace an existing user, fails with [`StoreError::NotFound`] if the username is unknown.
+    fn update(&self, user: User) -> Result<()>;
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryUserStore {
+    users: RwLock<HashMap<String, User>>,
+}
+
+/// A user store persisted as a json file, all users are kept in memory.
+#[derive(Debug)]
+pub struct FileUserStore {
+    file: StoreFile,
+    inner: MemoryUserStore,
+}
+
+impl MemoryUserStore {
+    fn snapshot(&self) -> Vec<User> {
+        let users = self.users.read().unwrap();
+        let mut users: Vec<_> = users.values().cloned().collect();
+        users.sort_by(|a, b| a.username.cmp(&b.username));
+        users
+    }
+}
+
+impl UserStore for MemoryUserStore {
+    fn get(&self, username: &str) -> Result<Option<User>> {
+        Ok(self.users.read().unwrap().get(username).cloned())
+    }
+
+    fn insert(&self, user: User) -> Result<()> {
+        let mut users = self.users.write().unwrap();
+        if users.contains_key(&user.username) {
+            return Err(StoreError::AlreadyExists(format!("user {}", user.username)));
+        }
+        users.insert(user.username.clone(), user);
+        Ok(())
+    }
+
+    fn update(&self, user: User) -> Result<()> {
+        let mut users = self.users.write().unwrap();
+        match users.get_mut(&user.username) {
+            Some(v) => *v = user,
+            None => return Err(StoreError::NotFound(format!("user {}", user.username))),
+        }
+        Ok(())
+    }
+}
+
+impl FileUserStore {
+    pub fn try_new(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let users: Vec<User> = load(&path)?;
+        let inner = MemoryUserStore {
+            users: RwLock::new(users.into_iter().map(|u| (u.username.clone(), u)).collect()),
+        };
+        Ok(Self {
+            file: StoreFile::new(path),
+            inner,
+        })
+    }
+}
+
+impl UserStore for FileUserStore {
+    fn get(&self, username: &str) -> Result<Option<User>> {
+        self.inner.get(username)
+    }
+
+    fn insert(&self, user: User) -> Result<()> {
+        let inner = &self.inner;
+        self.file
+            .commit(&inner.users, || inner.insert(user), || inner.snapshot())
+    }
+
+    fn update(&self, user: User) -> Result<()> {
+        let inner = &self.inner;
+        self.file
+            .commit(&inner.users, || inner.update(user), || inner.snapshot())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(username: &str) -> User {
+        User {
+            username: username.to_string(),
+            password_hash: "hash".to_string(),
+            roles: vec![],
+            disabled: false,
+            created_at: 0,
+            mfa: None,
+        }
+    }
+
+    #[test]
+    fn memory_store_should_reject_duplicated_user() {
+        let store = MemoryUserStore::default();
+        store.insert(user("alice")).unwrap();
+        assert!(matches!(
+            store.insert(user("alice")),
+            Err(StoreError::AlreadyExists(_))
+        ));
+        assert!(store.get("alice").unwrap().is_some());
+        assert!(store.get("bob").unwrap().is_none());
+    }
+
+    #[test]
+    fn file_store_should_persist_users() {
+        let path = std::env::temp_dir().join(format!("users-{}.json", uuid7::uuid7()));
+        let store = FileUserStore::try_new(&path).unwrap();
+        store.insert(user("alice")).unwrap();
+        let mut alice = user("alice");
+        alice.disabled = true;
+        store.update(alice).unwrap();
+
+        let store = FileUserStore::try_new(&path).unwrap();
+        assert!(store.get("alice").unwrap().unwrap().disabled);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn failed_write_should_roll_back_the_change() {
+        let dir = std::env::temp_dir().join(format!("missing-{}", uuid7::uuid7()));
+        let store = FileUserStore::try_new(dir.join("users.json")).unwrap();
+        assert!(matches!(
+            store.insert(user("alice")),
+            Err(StoreError::Io(_))
+        ));
+        assert!(store.get("alice").unwrap().is_none());
+    }
+}
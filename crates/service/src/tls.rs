@@ -0,0 +1,299 @@
+//! TLS termination with optional client certificate (mTLS) authentication.
+
+use crate::auth::{Identity, RoleConfig};
+use axum::{extract::ConnectInfo, http::Request, Router};
+use hyper::{service::service_fn, Body};
+use rustls::{
+    server::{AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient},
+    Certificate, PrivateKey, RootCertStore, ServerConfig,
+};
+use rustls_pemfile::Item;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::BufReader,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use thiserror::Error;
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tower::Service;
+use tracing::{debug, warn};
+
+/// Prefix of the usernames of client certificates, usernames of users can't contain `:`.
+pub(crate) const CERT_PREFIX: &str = "cert:";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// PEM file of the server certificate chain
+    pub cert: PathBuf,
+    /// PEM file of the server private key
+    pub key: PathBuf,
+    /// PEM file of the CA that signs client certificates, enables mTLS
+    #[serde(default)]
+    pub client_ca: Option<PathBuf>,
+    /// reject connections without a client certificate, otherwise other auth schemes may be used
+    #[serde(default)]
+    pub require_client_cert: bool,
+    /// roles of clients by the common name of their certificate
+    #[serde(default)]
+    pub client_roles: HashMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Error)]
+pub enum TlsError {
+    #[error("failed to read {}: {1}", .0.display())]
+    File(PathBuf, std::io::Error),
+    #[error("no private key in {}", .0.display())]
+    MissingKey(PathBuf),
+    #[error("invalid certificate: {0}")]
+    InvalidCertificate(String),
+    #[error("tls error: {0}")]
+    Rustls(#[from] rustls::Error),
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// Verified certificate of the client of a TLS connection, added to the extensions of its requests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientCertificate {
+    pub subject: String,
+    pub common_name: Option<String>,
+    /// hex encoded sha256 of the DER certificate
+    pub fingerprint: String,
+}
+
+impl TlsConfig {
+    pub fn server_config(&self) -> Result<ServerConfig, TlsError> {
+        let certs = load_certs(&self.cert)?;
+        let key = load_key(&self.key)?;
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let builder = match &self.client_ca {
+            Some(ca) => {
+                let mut roots = RootCertStore::empty();
+                for cert in load_certs(ca)? {
+                    roots
+                        .add(&cert)
+                        .map_err(|e| TlsError::InvalidCertificate(e.to_string()))?;
+                }
+                let verifier = match self.require_client_cert {
+                    true => AllowAnyAuthenticatedClient::new(roots).boxed(),
+                    false => AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed(),
+                };
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        Ok(builder.with_single_cert(certs, key)?)
+    }
+
+    /// Identity of a client certificate, named `cert:<common name>` so it can't be taken for
+    /// the user of the same name.
+    pub fn identity(&self, cert: &ClientCertificate, roles: &RoleConfig) -> Option<Identity> {
+        let common_name = cert.common_name.as_ref()?;
+        let client_roles = self
+            .client_roles
+            .get(common_name)
+            .cloned()
+            .unwrap_or_default();
+        Some(Identity {
+            username: format!("{}{}", CERT_PREFIX, common_name),
+            scopes: roles.scopes(&client_roles),
+            roles: client_roles,
+            certificate_subject: Some(cert.subject.clone()),
+            ..Default::default()
+        })
+    }
+}
+
+impl ClientCertificate {
+    fn parse(cert: &Certificate) -> Result<Self, TlsError> {
+        let (_, x509) = x509_parser::parse_x509_certificate(&cert.0)
+            .map_err(|e| TlsError::InvalidCertificate(e.to_string()))?;
+        let subject = x509.subject();
+        Ok(Self {
+            subject: subject.to_string(),
+            common_name: subject
+                .iter_common_name()
+                .next()
+                .and_then(|cn| cn.as_str().ok())
+                .map(ToString::to_string),
+            fingerprint: format!("{:x}", Sha256::digest(&cert.0)),
+        })
+    }
+}
+
+/// Serve the router over TLS, requests carry the [`ClientCertificate`] of their connection if any.
+pub async fn serve_tls(
+    router: Router,
+    addr: SocketAddr,
+    config: &TlsConfig,
+) -> Result<(), TlsError> {
+    let listener = TcpListener::bind(addr).await?;
+    serve_tls_on(listener, router, config).await
+}
+
+/// Serve the router over TLS on a bound listener, requests carry the [`ConnectInfo`] of their
+/// peer like the plain HTTP server does.
+async fn serve_tls_on(
+    listener: TcpListener,
+    router: Router,
+    config: &TlsConfig,
+) -> Result<(), TlsError> {
+    let acceptor = TlsAcceptor::from(Arc::new(config.server_config()?));
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                warn!("failed to accept connection: {}", e);
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let router = router.clone();
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    debug!("tls handshake with {} failed: {}", peer, e);
+                    return;
+                }
+            };
+            let (_, conn) = stream.get_ref();
+            let cert = conn
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .map(ClientCertificate::parse)
+                .transpose();
+            let cert = match cert {
+                Ok(cert) => cert,
+                Err(e) => {
+                    warn!("client certificate of {} rejected: {}", peer, e);
+                    return;
+                }
+            };
+
+            let service = service_fn(move |mut req: Request<Body>| {
+                req.extensions_mut().insert(ConnectInfo(peer));
+                if let Some(cert) = &cert {
+                    req.extensions_mut().insert(cert.clone());
+                }
+                router.clone().call(req)
+            });
+            if let Err(e) = hyper::server::conn::Http::new()
+                .serve_connection(stream, service)
+                .await
+            {
+                debug!("connection with {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+fn load_certs(path: &Path) -> Result<Vec<Certificate>, TlsError> {
+    let mut reader = open(path)?;
+    let certs =
+        rustls_pemfile::certs(&mut reader).map_err(|e| TlsError::File(path.to_path_buf(), e))?;
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn load_key(path: &Path) -> Result<PrivateKey, TlsError> {
+    let mut reader = open(path)?;
+    loop {
+        match rustls_pemfile::read_one(&mut reader) {
+            Ok(Some(Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key))) => {
+                return Ok(PrivateKey(key))
+            }
+            Ok(Some(_)) => continue,
+            Ok(None) => return Err(TlsError::MissingKey(path.to_path_buf())),
+            Err(e) => return Err(TlsError::File(path.to_path_buf(), e)),
+        }
+    }
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, TlsError> {
+    let file = File::open(path).map_err(|e| TlsError::File(path.to_path_buf(), e))?;
+    Ok(BufReader::new(file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+
+    #[test]
+    fn client_certificate_should_map_to_identity() {
+        let config = TlsConfig {
+            cert: "server.pem".into(),
+            key: "server.key".into(),
+            client_ca: None,
+            require_client_cert: false,
+            client_roles: HashMap::from([("gateway".to_string(), vec!["user".to_string()])]),
+        };
+        let cert = ClientCertificate {
+            subject: "CN=gateway, O=example".to_string(),
+            common_name: Some("gateway".to_string()),
+            fingerprint: "00".to_string(),
+        };
+        let identity = config.identity(&cert, &RoleConfig::default()).unwrap();
+        assert_eq!(identity.username, "cert:gateway");
+        assert_eq!(identity.scopes, vec!["echo:write"]);
+        assert_eq!(
+            identity.certificate_subject.as_deref(),
+            Some("CN=gateway, O=example")
+        );
+
+        let anonymous = ClientCertificate {
+            common_name: None,
+            ..cert
+        };
+        assert!(config
+            .identity(&anonymous, &RoleConfig::default())
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn tls_requests_should_carry_the_peer_address() {
+        let dir = std::env::temp_dir().join(format!("tls-{}", uuid7::uuid7()));
+        std::fs::create_dir(&dir).unwrap();
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let pem = cert.serialize_pem().unwrap();
+        std::fs::write(dir.join("server.pem"), &pem).unwrap();
+        std::fs::write(dir.join("server.key"), cert.serialize_private_key_pem()).unwrap();
+        let config = TlsConfig {
+            cert: dir.join("server.pem"),
+            key: dir.join("server.key"),
+            client_ca: None,
+            require_client_cert: false,
+            client_roles: HashMap::new(),
+        };
+
+        let app = Router::new().route(
+            "/peer",
+            get(|ConnectInfo(peer): ConnectInfo<SocketAddr>| async move { peer.ip().to_string() }),
+        );
+        // bound before spawning so the server accepts by the time the request is sent
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { serve_tls_on(listener, app, &config).await.unwrap() });
+
+        let client = reqwest::Client::builder()
+            .add_root_certificate(reqwest::Certificate::from_pem(pem.as_bytes()).unwrap())
+            .build()
+            .unwrap();
+        let res = client
+            .get(format!("https://localhost:{}/peer", addr.port()))
+            .send()
+            .await
+            .unwrap();
+        assert!(res.status().is_success());
+        assert_eq!(res.text().await.unwrap(), "127.0.0.1");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}